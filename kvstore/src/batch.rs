@@ -0,0 +1,160 @@
+// Batch scripts list operations to apply to the database in one transaction,
+// one per line:
+//
+//     # Comments and blank lines are ignored.
+//     set KEY VALUE           Fails if KEY already exists.
+//     set -f KEY VALUE        Overwrites KEY if it exists.
+//     remove KEY              Fails if KEY doesn't exist.
+//     check KEY VALUE         Fails unless KEY is set to VALUE.
+//     check KEY               Fails unless KEY exists.
+//     check --absent KEY      Fails if KEY exists.
+//     check --version N KEY   Fails unless KEY is at version N.
+//
+// Words are separated by whitespace. A word containing whitespace can be
+// single quoted, taken literally, or double quoted, where \\, \", \n, \r and
+// \t escapes are recognized.
+
+use crate::database::{DbError, Precondition, Transaction};
+
+// An operation in a batch script.
+pub enum Op {
+    Set {
+        key: String,
+        value: String,
+        force: bool,
+    },
+    Remove {
+        key: String,
+    },
+    Check {
+        key: String,
+        expected: Precondition,
+    },
+}
+
+// Parse a batch script into its operations, along with their line numbers.
+pub fn parse(script: &str) -> Result<Vec<(usize, Op)>, String> {
+    let mut ops = Vec::new();
+    for (i, line) in script.lines().enumerate() {
+        let line_number = i + 1;
+        let words = split_words(line).map_err(|e| format!("line {}: {}", line_number, e))?;
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+
+        let op =
+            match words.as_slice() {
+                [] => continue,
+                ["set", key, value] => Op::Set {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                    force: false,
+                },
+                ["set", "-f" | "--force", key, value] => Op::Set {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                    force: true,
+                },
+                ["remove", key] => Op::Remove {
+                    key: key.to_string(),
+                },
+                ["check", key] => Op::Check {
+                    key: key.to_string(),
+                    expected: Precondition::Present,
+                },
+                ["check", "--absent", key] => Op::Check {
+                    key: key.to_string(),
+                    expected: Precondition::Absent,
+                },
+                ["check", "--version", version, key] => Op::Check {
+                    key: key.to_string(),
+                    expected: Precondition::Version(version.parse().map_err(|_| {
+                        format!("line {}: invalid version '{}'", line_number, version)
+                    })?),
+                },
+                ["check", key, value] => Op::Check {
+                    key: key.to_string(),
+                    expected: Precondition::Value(value.as_bytes().to_vec()),
+                },
+                _ => {
+                    return Err(format!(
+                        "line {}: invalid operation '{}'",
+                        line_number, line
+                    ));
+                }
+            };
+        ops.push((line_number, op));
+    }
+
+    Ok(ops)
+}
+
+// Apply the operations of a batch script to a transaction, stopping at the
+// first one that fails.
+pub fn apply(tx: &mut Transaction, ops: Vec<(usize, Op)>) -> Result<(), DbError> {
+    for (line_number, op) in ops {
+        match op {
+            Op::Set { key, value, force } => {
+                tx.insert(key, value.into_bytes(), force)?;
+            }
+            Op::Remove { key } => {
+                if tx.remove(&key).is_none() {
+                    return Err(DbError::NotFound(key));
+                }
+            }
+            Op::Check { key, expected } => {
+                tx.check(&key, &expected).map_err(|e| match e {
+                    DbError::PreconditionFailed(reason) => {
+                        DbError::PreconditionFailed(format!("line {}: {}", line_number, reason))
+                    }
+                    e => e,
+                })?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Split a line into words, honouring quotes and stopping at a comment.
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match chars.peek() {
+            None | Some('#') => break,
+            _ => {}
+        }
+
+        let mut word = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+            match c {
+                '\'' => loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("missing closing quote".to_string()),
+                    }
+                },
+                '"' => loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => word.push('\n'),
+                            Some('r') => word.push('\r'),
+                            Some('t') => word.push('\t'),
+                            Some(c @ ('\\' | '"')) => word.push(c),
+                            _ => return Err("invalid escape sequence".to_string()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("missing closing quote".to_string()),
+                    }
+                },
+                _ => word.push(c),
+            }
+        }
+        words.push(word);
+    }
+
+    Ok(words)
+}
@@ -0,0 +1,103 @@
+// Running subcommands against a server started with `kvstore serve`, instead
+// of opening the database files directly.
+
+use crate::database::format::{self, Version};
+use crate::database::{DEFAULT_NAMESPACE, DbError};
+use crate::server::line::{self, Request};
+use crate::server::{self, Address, Connection};
+use crate::{Config, SubCommand, glob_match, output};
+use std::io::{Error, ErrorKind};
+
+// Carry out the subcommand in the config by sending requests to the server at
+// an address. Only get, set, remove, list and watch are supported.
+pub fn run(config: Config, address: &Address) -> Result<(), DbError> {
+    let mut connection = server::connect(address)?;
+    if config.namespace != DEFAULT_NAMESPACE {
+        send(&mut connection, Request::Ns(config.namespace.clone()))?;
+    }
+
+    match config.cmd {
+        SubCommand::Get { key } => {
+            let fields = send(&mut connection, Request::Get(key.clone()))?;
+            let [value, version] = fields.as_slice() else {
+                return Err(invalid_response());
+            };
+            let value =
+                format::unescape_bytes(value, Version::V2).map_err(|_| invalid_response())?;
+            let version = version.parse().map_err(|_| invalid_response())?;
+            output::found(config.output, &key, &value, version);
+            Ok(())
+        }
+        SubCommand::Set {
+            key,
+            value,
+            force,
+            ttl,
+            expected: None,
+        } => {
+            send(
+                &mut connection,
+                Request::Set {
+                    key,
+                    value,
+                    force,
+                    ttl,
+                },
+            )?;
+            Ok(())
+        }
+        SubCommand::Remove { key } => {
+            let fields = send(&mut connection, Request::Del(key.clone()))?;
+            let [value] = fields.as_slice() else {
+                return Err(invalid_response());
+            };
+            let value =
+                format::unescape_bytes(value, Version::V2).map_err(|_| invalid_response())?;
+            output::removed(config.output, &key, &value);
+            Ok(())
+        }
+        SubCommand::List {
+            prefix,
+            glob,
+            keys_only,
+        } => {
+            let fields = send(&mut connection, Request::List(prefix))?;
+            let count = match fields.as_slice() {
+                [count] => count.parse().map_err(|_| invalid_response())?,
+                _ => return Err(invalid_response()),
+            };
+            let entries = line::read_entries(&mut connection.0, count)?;
+            let entries = entries
+                .iter()
+                .filter(|(k, _)| glob.as_ref().is_none_or(|g| glob_match(g, k)))
+                .map(|(k, v)| (k, v.as_slice()));
+
+            output::entries(config.output, entries, keys_only);
+            Ok(())
+        }
+        SubCommand::Watch { target } => {
+            send(&mut connection, Request::Watch(target))?;
+            loop {
+                let event = line::read_event(&mut connection.0)?;
+                output::event(config.output, &event);
+            }
+        }
+        _ => Err(Error::new(
+            ErrorKind::Unsupported,
+            "Only get, set without preconditions, remove, list and watch can be used with --remote.",
+        )
+        .into()),
+    }
+}
+
+// Send a request and read the response, returning its fields.
+fn send(connection: &mut Connection, request: Request) -> Result<Vec<String>, DbError> {
+    let (reader, writer) = connection;
+    writer.write_all(request.to_line().as_bytes())?;
+    writer.flush()?;
+    line::read_response(reader)
+}
+
+fn invalid_response() -> DbError {
+    Error::new(ErrorKind::InvalidData, "Invalid response from the server.").into()
+}
@@ -0,0 +1,93 @@
+use super::{DbError, Namespaces, Record};
+use std::fmt;
+use std::str::FromStr;
+
+// Where a database is persisted. The database itself is always kept in memory
+// as a whole; a backend reads it in when the database is opened, then records
+// each change made to it.
+pub(super) trait Backend: Send {
+    // Durably record changes before they are applied to the database in
+    // memory, which is given as it is before them. A failure leaves both
+    // unchanged.
+    fn append(&mut self, records: &[Record], namespaces: &Namespaces) -> Result<(), DbError>;
+
+    // Called once changes have been applied, with the database as it is now,
+    // for backends that write it out as a whole from time to time.
+    fn applied(&mut self, namespaces: &Namespaces) -> Result<(), DbError>;
+
+    // Write the database out as a whole, replacing everything stored so far.
+    // Expired entries are left out, since they'd be ignored when read back.
+    fn write_all(&mut self, namespaces: &Namespaces) -> Result<(), DbError>;
+
+    // Whether changes have been recorded that 'write_all' would still tidy up.
+    fn is_dirty(&self) -> bool;
+}
+
+/// The kinds of backend a database can be stored with, trading durability
+/// for speed and convenience in different ways.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    /// A TSV file along with a write-ahead log, the default. Each change is
+    /// appended to the log, which is compacted into the file now and then.
+    /// The only backend that supports encryption and repair.
+    Tsv,
+    /// A JSON file, rewritten as a whole on each change. Easy to read and
+    /// edit by hand, but slow for large databases.
+    Json,
+    /// An SQLite database file, where each change is its own transaction.
+    Sqlite,
+    /// Nothing is stored at all, so the database starts out empty every
+    /// time it's opened. Useful for a server that needn't outlive its process.
+    Memory,
+}
+
+impl BackendKind {
+    pub const NAMES: [&'static str; 4] = ["tsv", "json", "sqlite", "memory"];
+}
+
+impl FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tsv" => Ok(BackendKind::Tsv),
+            "json" => Ok(BackendKind::Json),
+            "sqlite" => Ok(BackendKind::Sqlite),
+            "memory" => Ok(BackendKind::Memory),
+            _ => Err(format!("Unknown backend '{}'.", s)),
+        }
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BackendKind::Tsv => "tsv",
+            BackendKind::Json => "json",
+            BackendKind::Sqlite => "sqlite",
+            BackendKind::Memory => "memory",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// A backend that keeps nothing once the database is dropped.
+pub(super) struct Memory;
+
+impl Backend for Memory {
+    fn append(&mut self, _records: &[Record], _namespaces: &Namespaces) -> Result<(), DbError> {
+        Ok(())
+    }
+
+    fn applied(&mut self, _namespaces: &Namespaces) -> Result<(), DbError> {
+        Ok(())
+    }
+
+    fn write_all(&mut self, _namespaces: &Namespaces) -> Result<(), DbError> {
+        Ok(())
+    }
+
+    fn is_dirty(&self) -> bool {
+        false
+    }
+}
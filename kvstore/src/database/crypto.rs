@@ -0,0 +1,154 @@
+// Authenticated encryption of the database files.
+//
+// An encrypted file starts with a header line giving the key derivation
+// function, its parameters and the salt, separated by tabs:
+//
+//     #kvstore encrypted<TAB>argon2id<TAB>m=19456,t=2,p=1<TAB><salt>
+//
+// Each line after the header is a record sealed with XChaCha20-Poly1305 and
+// written as the base64 of its random nonce followed by the ciphertext. The
+// database file is sealed as a single record holding its usual contents,
+// while each record in the write-ahead log is sealed separately so the log
+// can still be appended to.
+
+use argon2::{Algorithm, Argon2, Params};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+// Start of the first line of every encrypted file.
+const HEADER_PREFIX: &str = "#kvstore encrypted\t";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+// Associated data that ties sealed records to the kind of file they are in,
+// so records can't be moved from one to the other.
+pub const DATABASE_FILE: &[u8] = b"kvstore database";
+pub const LOG_FILE: &[u8] = b"kvstore log";
+
+// Key used to seal and open the records of encrypted files.
+#[derive(Clone)]
+pub struct Key {
+    cipher: XChaCha20Poly1305,
+    params: Params,
+    salt: Vec<u8>,
+}
+
+// Whether file contents are encrypted.
+pub fn is_encrypted(contents: &str) -> bool {
+    contents.starts_with(HEADER_PREFIX)
+}
+
+impl Key {
+    // Derive a key from a passphrase with a new random salt.
+    pub fn generate(passphrase: &[u8]) -> std::io::Result<Key> {
+        let mut salt = vec![0; SALT_LEN];
+        getrandom::fill(&mut salt).map_err(std::io::Error::other)?;
+        Key::derive(passphrase, Params::default(), salt).map_err(std::io::Error::other)
+    }
+
+    // Derive the key for an encrypted file from a passphrase and the
+    // parameters in its header. Returns the key along with the sealed records
+    // that follow the header.
+    pub fn from_header<'a>(contents: &'a str, passphrase: &[u8]) -> Result<(Key, &'a str), String> {
+        let (params, salt, records) = parse_header(contents)?;
+        let key = Key::derive(passphrase, params, salt)?;
+        Ok((key, records))
+    }
+
+    fn derive(passphrase: &[u8], params: Params, salt: Vec<u8>) -> Result<Key, String> {
+        let argon2 = Argon2::new(Algorithm::Argon2id, argon2::Version::V0x13, params.clone());
+        let mut key = [0; 32];
+        argon2
+            .hash_password_into(passphrase, &salt, &mut key)
+            .map_err(|e| format!("can't derive key: {}", e))?;
+        Ok(Key {
+            cipher: XChaCha20Poly1305::new(&key.into()),
+            params,
+            salt,
+        })
+    }
+
+    // Header line for files encrypted with this key.
+    pub fn header(&self) -> String {
+        format!(
+            "{}argon2id\tm={},t={},p={}\t{}\n",
+            HEADER_PREFIX,
+            self.params.m_cost(),
+            self.params.t_cost(),
+            self.params.p_cost(),
+            BASE64.encode(&self.salt)
+        )
+    }
+
+    // Seal a record so it can be written as a single line.
+    pub fn seal(&self, record: &str, file: &[u8]) -> std::io::Result<String> {
+        let mut nonce = [0; NONCE_LEN];
+        getrandom::fill(&mut nonce).map_err(std::io::Error::other)?;
+        let payload = Payload {
+            msg: record.as_bytes(),
+            aad: file,
+        };
+        let ciphertext = self
+            .cipher
+            .encrypt(XNonce::from_slice(&nonce), payload)
+            .map_err(|_| std::io::Error::other("can't encrypt record"))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        Ok(BASE64.encode(sealed))
+    }
+
+    // Open a record sealed by 'seal'. Fails if the record was sealed with a
+    // different key or has been changed since.
+    pub fn open(&self, line: &str, file: &[u8]) -> Result<String, String> {
+        let sealed = BASE64
+            .decode(line)
+            .map_err(|_| "invalid encrypted record".to_string())?;
+        if sealed.len() < NONCE_LEN {
+            return Err("invalid encrypted record".to_string());
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: file,
+        };
+        let record = self
+            .cipher
+            .decrypt(XNonce::from_slice(nonce), payload)
+            .map_err(|_| "can't decrypt record".to_string())?;
+        String::from_utf8(record).map_err(|_| "can't decrypt record".to_string())
+    }
+}
+
+// Parse the header of an encrypted file into the key derivation parameters
+// and salt, returning them along with the records that follow.
+fn parse_header(contents: &str) -> Result<(Params, Vec<u8>, &str), String> {
+    let invalid = || "invalid encryption header".to_string();
+    let (header, records) = contents
+        .strip_prefix(HEADER_PREFIX)
+        .and_then(|rest| rest.split_once('\n'))
+        .ok_or_else(invalid)?;
+
+    let ["argon2id", params, salt] = header.split('\t').collect::<Vec<_>>()[..] else {
+        return Err(invalid());
+    };
+    let (mut m_cost, mut t_cost, mut p_cost) = (None, None, None);
+    for param in params.split(',') {
+        match param.split_once('=') {
+            Some(("m", value)) => m_cost = value.parse().ok(),
+            Some(("t", value)) => t_cost = value.parse().ok(),
+            Some(("p", value)) => p_cost = value.parse().ok(),
+            _ => return Err(invalid()),
+        }
+    }
+    let (Some(m_cost), Some(t_cost), Some(p_cost)) = (m_cost, t_cost, p_cost) else {
+        return Err(invalid());
+    };
+    let params = Params::new(m_cost, t_cost, p_cost, None).map_err(|_| invalid())?;
+    let salt = BASE64.decode(salt).map_err(|_| invalid())?;
+
+    Ok((params, salt, records))
+}
@@ -0,0 +1,104 @@
+use std::fmt;
+
+/// Errors returned by database operations.
+#[derive(Debug)]
+pub enum DbError {
+    /// Reading or writing the database files failed.
+    Io(std::io::Error),
+    /// A line in a database file could not be parsed.
+    Corrupt {
+        path: String,
+        line: usize,
+        reason: String,
+    },
+    /// The key is already in the database.
+    AlreadyExists(String),
+    /// The key is not in the database.
+    NotFound(String),
+    /// The namespace is not in the database.
+    NamespaceNotFound(String),
+    /// The file is encrypted, but no passphrase was given.
+    KeyRequired(String),
+    /// The file couldn't be decrypted with the passphrase given.
+    WrongKey(String),
+    /// Another process holds a conflicting lock on the database.
+    Locked(String),
+    /// The version isn't in the key's history. No version means the key has
+    /// no earlier versions at all.
+    VersionNotFound { key: String, version: Option<u64> },
+    /// A condition the change depended on didn't hold, so nothing was changed.
+    PreconditionFailed(String),
+    /// A kvstore server failed to carry out a request, with the exit code
+    /// and message it gave for the error.
+    Remote { code: i32, message: String },
+}
+
+impl DbError {
+    /// Process exit code used to report this error from the CLI.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            DbError::Io(_) => 1,
+            DbError::NotFound(_) => 3,
+            DbError::AlreadyExists(_) => 4,
+            DbError::Corrupt { .. } => 5,
+            DbError::Locked(_) => 6,
+            DbError::PreconditionFailed(_) => 7,
+            DbError::VersionNotFound { .. } => 8,
+            DbError::NamespaceNotFound(_) => 9,
+            DbError::KeyRequired(_) => 10,
+            DbError::WrongKey(_) => 11,
+            DbError::Remote { code, .. } => *code,
+        }
+    }
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::Io(e) => write!(f, "{}", e),
+            DbError::Corrupt { path, line, reason } => write!(
+                f,
+                "{}:{}: {} (run with --repair to quarantine corrupt lines)",
+                path, line, reason
+            ),
+            DbError::AlreadyExists(key) => write!(f, "{} already exists in database.", key),
+            DbError::NotFound(key) => write!(f, "No entry found for key '{}'.", key),
+            DbError::NamespaceNotFound(name) => write!(f, "No namespace named '{}'.", name),
+            DbError::KeyRequired(path) => write!(
+                f,
+                "{} is encrypted; set KVSTORE_PASSPHRASE or use --key-file.",
+                path
+            ),
+            DbError::WrongKey(path) => write!(
+                f,
+                "Can't decrypt {}: wrong passphrase or key file, or the file was tampered with.",
+                path
+            ),
+            DbError::Locked(path) => write!(f, "{} is locked by another process.", path),
+            DbError::VersionNotFound {
+                key,
+                version: Some(version),
+            } => write!(f, "Version {} of '{}' isn't in its history.", version, key),
+            DbError::VersionNotFound { key, version: None } => {
+                write!(f, "'{}' has no earlier versions.", key)
+            }
+            DbError::PreconditionFailed(reason) => write!(f, "Precondition failed: {}", reason),
+            DbError::Remote { message, .. } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for DbError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DbError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for DbError {
+    fn from(e: std::io::Error) -> Self {
+        DbError::Io(e)
+    }
+}
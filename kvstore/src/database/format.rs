@@ -0,0 +1,93 @@
+// On-disk record format shared by the database file and the write-ahead log.
+//
+// Version 1 files have no header and store keys and values as-is, so a key or
+// value containing a tab or newline can't be read back. Version 2 files start
+// with a header line and escape backslashes, tabs, carriage returns and
+// newlines, so fields never contain the separators used between them. Bytes
+// that aren't part of valid UTF-8 are escaped as `\xHH`, so values can hold
+// arbitrary binary data.
+// A value in a version 2 file may be followed by `name=value` attributes,
+// such as `expires=<unix time in milliseconds>`.
+
+// First line of every version 2 file.
+pub const HEADER: &str = "#kvstore v2\n";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    V1,
+    V2,
+}
+
+// Detect the format version of a file's contents and return the records
+// that follow the header, if any.
+pub fn split_header(contents: &str) -> (Version, &str) {
+    match contents.strip_prefix(HEADER) {
+        Some(records) => (Version::V2, records),
+        None => (Version::V1, contents),
+    }
+}
+
+// Escape a key or value so it can be written as a single field.
+pub fn escape(field: &str) -> String {
+    escape_bytes(field.as_bytes())
+}
+
+// Escape a binary value so it can be written as a single field.
+pub fn escape_bytes(field: &[u8]) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for chunk in field.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '\t' => escaped.push_str("\\t"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                _ => escaped.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            escaped.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    escaped
+}
+
+// Reverse 'escape'. Fields from version 1 files are returned unchanged.
+pub fn unescape(field: &str, version: Version) -> Result<String, String> {
+    String::from_utf8(unescape_bytes(field, version)?)
+        .map_err(|_| format!("invalid UTF-8 in '{}'", field))
+}
+
+// Reverse 'escape_bytes'.
+pub fn unescape_bytes(field: &str, version: Version) -> Result<Vec<u8>, String> {
+    if version == Version::V1 {
+        return Ok(field.as_bytes().to_vec());
+    }
+
+    let mut unescaped = Vec::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            unescaped.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => unescaped.push(b'\\'),
+            Some('t') => unescaped.push(b'\t'),
+            Some('n') => unescaped.push(b'\n'),
+            Some('r') => unescaped.push(b'\r'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) if hex.len() == 2 && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+                        unescaped.push(byte)
+                    }
+                    _ => return Err(format!("invalid escape sequence in '{}'", field)),
+                }
+            }
+            _ => return Err(format!("invalid escape sequence in '{}'", field)),
+        }
+    }
+    Ok(unescaped)
+}
@@ -0,0 +1,211 @@
+// A backend storing the database as a JSON file, which is rewritten as a
+// whole with every change, so it's always easy to read:
+//
+//     {
+//       "namespaces": {
+//         "default": {
+//           "key": { "value": "text", "version": 2, "updated": 1700000000000,
+//                    "history": [{ "version": 1, "value": "old", "replaced": 1700000000000 }] }
+//         }
+//       }
+//     }
+//
+// Values that aren't valid UTF-8 are given as "base64" instead of "value".
+// Times are Unix times in milliseconds, and "expires" is when a key expires.
+
+use super::backend::Backend;
+use super::write_atomically;
+use super::{DbError, Entry, Lock, Namespaces, Record, Revision, new_namespaces, now};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde_json::{Map, Value, json};
+use std::fs;
+use std::io::ErrorKind;
+
+pub(super) struct JsonFile {
+    path: String,
+}
+
+impl JsonFile {
+    // Read the database from a JSON file. A writer creates the file if it
+    // doesn't exist, while a reader reads it as empty.
+    pub(super) fn open(path: &str, lock: Lock) -> Result<(JsonFile, Namespaces), DbError> {
+        let mut file = JsonFile {
+            path: path.to_string(),
+        };
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let namespaces = new_namespaces();
+                if lock == Lock::Exclusive {
+                    file.write_all(&namespaces)?;
+                }
+                return Ok((file, namespaces));
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let corrupt = |line, reason| DbError::Corrupt {
+            path: path.to_string(),
+            line,
+            reason,
+        };
+        let value: Value = serde_json::from_str(&contents)
+            .map_err(|e| corrupt(e.line(), format!("invalid JSON: {}", e)))?;
+        let namespaces = parse_namespaces(&value).map_err(|reason| corrupt(1, reason))?;
+
+        Ok((file, namespaces))
+    }
+}
+
+impl Backend for JsonFile {
+    // The file is written as a whole, so the changes are made to a copy of
+    // the database for it, leaving the database in memory as it is if the
+    // file can't be written.
+    fn append(&mut self, records: &[Record], namespaces: &Namespaces) -> Result<(), DbError> {
+        let mut namespaces = namespaces.clone();
+        for record in records {
+            record.clone().apply(&mut namespaces);
+        }
+        self.write_all(&namespaces)
+    }
+
+    fn applied(&mut self, _namespaces: &Namespaces) -> Result<(), DbError> {
+        Ok(())
+    }
+
+    fn write_all(&mut self, namespaces: &Namespaces) -> Result<(), DbError> {
+        let now = now();
+        let namespaces: Map<String, Value> = namespaces
+            .iter()
+            .map(|(name, keys)| {
+                let keys: Map<String, Value> = keys
+                    .iter()
+                    .filter(|(_, entry)| !entry.is_expired(now))
+                    .map(|(key, entry)| (key.clone(), entry_json(entry)))
+                    .collect();
+                (name.clone(), Value::Object(keys))
+            })
+            .collect();
+
+        let contents = serde_json::to_string_pretty(&json!({ "namespaces": namespaces }))
+            .map_err(std::io::Error::other)?;
+        Ok(write_atomically(&self.path, (contents + "\n").as_bytes())?)
+    }
+
+    fn is_dirty(&self) -> bool {
+        false
+    }
+}
+
+// Parse the namespaces of a database, making sure the default one is there.
+fn parse_namespaces(value: &Value) -> Result<Namespaces, String> {
+    let Some(names) = value.get("namespaces").and_then(Value::as_object) else {
+        return Err("expected an object with \"namespaces\"".to_string());
+    };
+
+    let mut namespaces = new_namespaces();
+    for (name, keys) in names {
+        let Some(keys) = keys.as_object() else {
+            return Err(format!("namespace '{}' isn't an object", name));
+        };
+        if name.is_empty() {
+            return Err("empty namespace name".to_string());
+        }
+        let map = namespaces.entry(name.clone()).or_default();
+        for (key, entry) in keys {
+            let entry =
+                parse_entry(entry).map_err(|reason| format!("key '{}': {}", key, reason))?;
+            map.insert(key.clone(), entry);
+        }
+    }
+    Ok(namespaces)
+}
+
+fn parse_entry(value: &Value) -> Result<Entry, String> {
+    let mut entry = Entry::new(parse_value(value)?);
+    if let Some(version) = value.get("version") {
+        entry.version = version
+            .as_u64()
+            .filter(|&version| version > 0)
+            .ok_or_else(|| format!("invalid version '{}'", version))?;
+    }
+    entry.expires_at = parse_time(value, "expires")?;
+    entry.updated_at = parse_time(value, "updated")?;
+
+    let history = match value.get("history") {
+        Some(history) => history.as_array().ok_or("invalid history")?.as_slice(),
+        None => &[],
+    };
+    for revision in history {
+        let invalid = || format!("invalid revision '{}'", revision);
+        entry.history.push(Revision {
+            version: revision
+                .get("version")
+                .and_then(Value::as_u64)
+                .ok_or_else(invalid)?,
+            value: parse_value(revision)?,
+            replaced_at: parse_time(revision, "replaced")?.ok_or_else(invalid)?,
+        });
+    }
+    Ok(entry)
+}
+
+// The value of an entry or revision, given as text or in base64.
+fn parse_value(value: &Value) -> Result<Vec<u8>, String> {
+    match (value.get("value"), value.get("base64")) {
+        (Some(Value::String(text)), None) => Ok(text.clone().into_bytes()),
+        (None, Some(Value::String(encoded))) => BASE64
+            .decode(encoded)
+            .map_err(|_| "invalid base64".to_string()),
+        _ => Err("expected a \"value\" or \"base64\" string".to_string()),
+    }
+}
+
+fn parse_time(value: &Value, name: &str) -> Result<Option<u64>, String> {
+    match value.get(name) {
+        None => Ok(None),
+        Some(time) => match time.as_u64() {
+            Some(millis) => Ok(Some(millis)),
+            None => Err(format!("invalid {} time '{}'", name, time)),
+        },
+    }
+}
+
+// Format an entry the way 'parse_entry' reads it. Like the TSV file, the
+// first version of a key is left implicit.
+fn entry_json(entry: &Entry) -> Value {
+    let mut object = value_json(&entry.value);
+    if let Some(expires_at) = entry.expires_at {
+        object.insert("expires".to_string(), json!(expires_at));
+    }
+    if entry.version > 1 {
+        object.insert("version".to_string(), json!(entry.version));
+    }
+    if let Some(updated_at) = entry.updated_at {
+        object.insert("updated".to_string(), json!(updated_at));
+    }
+    if !entry.history.is_empty() {
+        let history: Vec<Value> = entry
+            .history
+            .iter()
+            .map(|revision| {
+                let mut object = value_json(&revision.value);
+                object.insert("version".to_string(), json!(revision.version));
+                object.insert("replaced".to_string(), json!(revision.replaced_at));
+                Value::Object(object)
+            })
+            .collect();
+        object.insert("history".to_string(), json!(history));
+    }
+    Value::Object(object)
+}
+
+fn value_json(value: &[u8]) -> Map<String, Value> {
+    let mut object = Map::new();
+    match std::str::from_utf8(value) {
+        Ok(text) => object.insert("value".to_string(), json!(text)),
+        Err(_) => object.insert("base64".to_string(), json!(BASE64.encode(value))),
+    };
+    object
+}
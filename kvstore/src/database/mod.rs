@@ -1,101 +1,822 @@
-use std::collections::HashMap;
-use std::fs::OpenOptions;
-use std::io::{Error, ErrorKind, Read, Write};
-use std::panic;
+//! A key/value database kept in memory and persisted by one of several
+//! backends, by default a file with a write-ahead log that makes each change
+//! durable before it's applied. Open one with [`Database::open`] or
+//! [`Database::from_disk`], or use the [`Store`] trait to work with it and
+//! with a [`MemoryStore`] alike.
 
+pub use backend::BackendKind;
+use backend::{Backend, Memory};
+pub use error::DbError;
+use json::JsonFile;
+use sqlite::SqliteFile;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{Error, ErrorKind, Write};
+use std::ops::{Bound, RangeBounds};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+pub use store::{MemoryStore, Store};
+pub use transaction::Transaction;
+use tsv::TsvFile;
+pub(crate) use tsv::quarantine_filename;
+
+mod backend;
+mod crypto;
+mod error;
+pub(crate) mod format;
+mod json;
+mod sqlite;
+mod store;
+mod transaction;
+mod tsv;
+
+// Number of earlier values kept for each key.
+const HISTORY_LIMIT: usize = 10;
+
+/// Namespace that keys belong to unless another one is selected. It always
+/// exists and can't be dropped.
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+// Key/value pairs ordered by key, in namespaces ordered by name.
+type Namespaces = BTreeMap<String, BTreeMap<String, Entry>>;
+
+/// A key/value database held in memory, along with the backend that persists
+/// the changes made to it.
 pub struct Database {
-    map: HashMap<String, String>, // Where key/value pairs are stored
-    db_filename: String,          // Filename that key/value database is persisted to
+    namespaces: Namespaces,    // Where key/value pairs are stored
+    namespace: String,         // Namespace that keys are read from and written to
+    backend: Box<dyn Backend>, // Where changes are persisted
+    lock: Lock,                // How the database is locked while it is open
+    _lock_file: Option<File>,  // Holds the lock until the database is dropped
 }
 
-// Ensure the database contents are persisted back to disk when the instance is dropped.
-impl Drop for Database {
-    fn drop(&mut self) {
-        if let Err(e) = self.flush() {
-            panic!("Error writing to database file. Error: {}", e);
+// A value stored in the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    value: Vec<u8>,
+    expires_at: Option<u64>, // Unix time in milliseconds after which the entry is gone
+    version: u64,            // Starts at 1 when the key is created, then counts each set
+    updated_at: Option<u64>, // Unix time in milliseconds the value replaced an earlier one
+    history: Vec<Revision>,  // Earlier values, oldest first
+}
+
+/// An earlier value of a key, kept after it was overwritten.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Revision {
+    pub version: u64,
+    pub value: Vec<u8>,
+    /// Unix time in milliseconds the next version was set.
+    pub replaced_at: u64,
+}
+
+impl Entry {
+    fn new(value: Vec<u8>) -> Entry {
+        Entry {
+            value,
+            expires_at: None,
+            version: 1,
+            updated_at: None,
+            history: Vec::new(),
+        }
+    }
+
+    fn expiring(value: Vec<u8>, ttl: Duration) -> Entry {
+        Entry {
+            expires_at: Some(
+                now().saturating_add(u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX)),
+            ),
+            ..Entry::new(value)
+        }
+    }
+
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    // Make this the next version of a key, given its current entry.
+    fn replacing(self, current: Option<&Entry>) -> Entry {
+        match current {
+            Some(current) => Entry {
+                version: current.version + 1,
+                updated_at: Some(now()),
+                ..self
+            },
+            None => Entry {
+                version: 1,
+                updated_at: None,
+                ..self
+            },
+        }
+    }
+}
+
+/// A condition on the current state of a key that a change depends on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Precondition {
+    /// The key must not exist.
+    Absent,
+    /// The key must exist.
+    Present,
+    /// The key must be set to this value.
+    Value(Vec<u8>),
+    /// The key must be at this version.
+    Version(u64),
+}
+
+impl Precondition {
+    // Check the condition against the current entry for a key, failing with
+    // 'DbError::PreconditionFailed' if it doesn't hold.
+    fn check(&self, key: &str, current: Option<&Entry>) -> Result<(), DbError> {
+        let reason = match (self, current) {
+            (Precondition::Absent, None) | (Precondition::Present, Some(_)) => return Ok(()),
+            (Precondition::Absent, Some(_)) => format!("'{}' exists.", key),
+            (_, None) => format!("'{}' doesn't exist.", key),
+            // The value is left out, since it may be a secret or binary.
+            (Precondition::Value(expected), Some(entry)) if entry.value != *expected => {
+                format!("'{}' doesn't hold the expected value.", key)
+            }
+            (Precondition::Version(expected), Some(entry)) if entry.version != *expected => {
+                format!(
+                    "'{}' is at version {}, not {}.",
+                    key, entry.version, expected
+                )
+            }
+            _ => return Ok(()),
+        };
+        Err(DbError::PreconditionFailed(reason))
+    }
+}
+
+/// How a database is locked against other processes while it is open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lock {
+    /// Any number of processes may read the database at the same time.
+    Shared,
+    /// A single process may read and modify the database.
+    Exclusive,
+}
+
+// A change to the database, as a backend records it, such as a record in the
+// write-ahead log. Keys are given along with the namespace they belong to.
+#[derive(Clone)]
+enum Record {
+    Set(String, String, Entry),
+    Remove(String, String),
+    CreateNamespace(String),
+    DropNamespace(String),
+    // The records between a begin and a commit record make up a transaction.
+    Begin,
+    Commit,
+}
+
+impl Record {
+    fn from_change(namespace: String, key: String, change: Option<Entry>) -> Record {
+        match change {
+            Some(entry) => Record::Set(namespace, key, entry),
+            None => Record::Remove(namespace, key),
+        }
+    }
+
+    // Apply the record to the namespaces. A set record that replaces an
+    // earlier version of a key moves that version into the key's history.
+    // Replaying records that are already in the database, as happens after a
+    // crash between writing the database file and truncating the log, leaves
+    // it as it was: versions from the record's on are dropped from the
+    // history rather than added to it again.
+    fn apply(self, namespaces: &mut Namespaces) {
+        match self {
+            Record::Set(namespace, key, mut entry) => {
+                let map = namespaces.entry(namespace).or_default();
+                if entry.version > 1
+                    && let Some(previous) = map.remove(&key)
+                {
+                    entry.history = previous.history;
+                    entry
+                        .history
+                        .retain(|revision| revision.version < entry.version);
+                    if previous.version < entry.version {
+                        entry.history.push(Revision {
+                            version: previous.version,
+                            value: previous.value,
+                            replaced_at: entry.updated_at.unwrap_or(0),
+                        });
+                    }
+                    let excess = entry.history.len().saturating_sub(HISTORY_LIMIT);
+                    entry.history.drain(..excess);
+                }
+                map.insert(key, entry);
+            }
+            Record::Remove(namespace, key) => {
+                if let Some(map) = namespaces.get_mut(&namespace) {
+                    map.remove(&key);
+                }
+            }
+            Record::CreateNamespace(namespace) => {
+                namespaces.entry(namespace).or_default();
+            }
+            Record::DropNamespace(namespace) => {
+                if namespace != DEFAULT_NAMESPACE {
+                    namespaces.remove(&namespace);
+                }
+            }
+            Record::Begin | Record::Commit => {}
         }
     }
 }
 
 impl Database {
-    // Read a key/value database from disk into memory.
-    // If the specified file doesn't exist, create it.
-    pub fn from_disk(path: &str) -> Result<Database, Error> {
-        // Open existing or create a new key/value database file.
-        let mut file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .truncate(false)
-            .open(path)?;
+    /// Open a database stored at a path with one of the backends, reading it
+    /// into memory. Its files are created if they don't exist.
+    /// The database stays locked until it is dropped. If another process holds
+    /// a conflicting lock, wait for it to be released when `wait` is true and
+    /// fail with [`DbError::Locked`] otherwise.
+    /// Only the TSV backend supports encryption; the others fail if given a
+    /// passphrase.
+    pub fn open(
+        path: &str,
+        backend: BackendKind,
+        lock: Lock,
+        wait: bool,
+        passphrase: Option<&[u8]>,
+    ) -> Result<Database, DbError> {
+        let (db, _) = Database::load(path, backend, lock, wait, false, passphrase)?;
+        Ok(db)
+    }
+
+    /// Read a key/value database from disk into memory, like
+    /// [`Database::open`]. With the TSV backend, the write-ahead log is
+    /// replayed on top of the snapshot.
+    /// An encrypted database needs the passphrase it was encrypted with. Given
+    /// a passphrase, a writer encrypts a database that isn't encrypted yet.
+    pub fn from_disk(
+        path: &str,
+        backend: BackendKind,
+        lock: Lock,
+        wait: bool,
+        passphrase: Option<&[u8]>,
+    ) -> Result<Database, DbError> {
+        Database::open(path, backend, lock, wait, passphrase)
+    }
+
+    /// Open a database for reading only, like [`Database::from_disk`] with
+    /// [`Lock::Shared`]. Neither the database files nor its lock file are
+    /// ever created or written to, and a database that doesn't exist yet is
+    /// read as empty.
+    pub fn open_read_only(
+        path: &str,
+        backend: BackendKind,
+        wait: bool,
+        passphrase: Option<&[u8]>,
+    ) -> Result<Database, DbError> {
+        Database::from_disk(path, backend, Lock::Shared, wait, passphrase)
+    }
+
+    /// Like [`Database::from_disk`], but lines that can't be parsed are
+    /// skipped and moved to a quarantine file next to the database instead
+    /// of failing. Only the TSV backend can be repaired; the others fail.
+    /// Returns the database, locked exclusively, along with the number of
+    /// lines quarantined.
+    pub fn repair(
+        path: &str,
+        backend: BackendKind,
+        wait: bool,
+        passphrase: Option<&[u8]>,
+    ) -> Result<(Database, usize), DbError> {
+        Database::load(path, backend, Lock::Exclusive, wait, true, passphrase)
+    }
+
+    fn load(
+        path: &str,
+        kind: BackendKind,
+        lock: Lock,
+        wait: bool,
+        repair: bool,
+        passphrase: Option<&[u8]>,
+    ) -> Result<(Database, usize), DbError> {
+        if passphrase.is_some() && kind != BackendKind::Tsv {
+            return Err(DbError::Io(Error::new(
+                ErrorKind::Unsupported,
+                format!("The {} backend doesn't support encryption.", kind),
+            )));
+        }
+        if repair && kind != BackendKind::Tsv {
+            return Err(DbError::Io(Error::new(
+                ErrorKind::Unsupported,
+                format!("The {} backend can't be repaired.", kind),
+            )));
+        }
+
+        // The memory backend has no files for other processes to share.
+        let lock_file = match kind {
+            BackendKind::Memory => None,
+            _ => acquire_lock(path, lock, wait)?,
+        };
 
-        // Read contents of file as a single long String.
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)?;
+        let (backend, namespaces, quarantined): (Box<dyn Backend>, _, _) = match kind {
+            BackendKind::Tsv => {
+                let (file, namespaces, quarantined) =
+                    TsvFile::open(path, lock, repair, passphrase)?;
+                (Box::new(file), namespaces, quarantined)
+            }
+            BackendKind::Json => {
+                let (file, namespaces) = JsonFile::open(path, lock)?;
+                (Box::new(file), namespaces, 0)
+            }
+            BackendKind::Sqlite => {
+                let (file, namespaces) = SqliteFile::open(path, lock)?;
+                (Box::new(file), namespaces, 0)
+            }
+            BackendKind::Memory => (Box::new(Memory), new_namespaces(), 0),
+        };
 
-        // Populate a hashmap in memory of the file's contents.
-        let mut hashmap = HashMap::new();
-        for line in contents.lines() {
-            let mut chunks = line.split('\t');
-            let key = chunks.next().unwrap();
-            let value = chunks.next().unwrap();
+        let db = Database {
+            namespaces,
+            namespace: DEFAULT_NAMESPACE.to_string(),
+            backend,
+            lock,
+            _lock_file: lock_file,
+        };
+        Ok((db, quarantined))
+    }
+
+    /// Names of the namespaces in the database, in order.
+    pub fn namespaces(&self) -> impl Iterator<Item = &String> {
+        self.namespaces.keys()
+    }
 
-            hashmap.insert(key.to_string(), value.to_string());
+    /// Select the namespace that keys are read from and written to.
+    pub fn use_namespace(&mut self, name: &str) -> Result<(), DbError> {
+        if !self.namespaces.contains_key(name) {
+            return Err(DbError::NamespaceNotFound(name.to_string()));
         }
+        self.namespace = name.to_string();
 
-        // Instantiate a new instance of Database and return to caller.
-        Ok(Database {
-            map: hashmap,
-            db_filename: path.to_string(),
-        })
+        Ok(())
+    }
+
+    /// Create a new, empty namespace.
+    pub fn create_namespace(&mut self, name: &str) -> Result<(), DbError> {
+        self.check_writable()?;
+        if name.is_empty() {
+            return Err(DbError::Io(Error::new(
+                ErrorKind::InvalidInput,
+                "Namespace names can't be empty.",
+            )));
+        }
+        if self.namespaces.contains_key(name) {
+            return Err(DbError::AlreadyExists(name.to_string()));
+        }
+        self.write(vec![Record::CreateNamespace(name.to_string())])
     }
 
-    pub fn get(&self, key: &str) -> Option<(&String, &String)> {
-        self.map.get_key_value(key)
+    /// Remove a namespace along with all of its keys. If it was selected, the
+    /// default namespace is selected instead.
+    /// Returns the number of keys removed.
+    pub fn drop_namespace(&mut self, name: &str) -> Result<usize, DbError> {
+        self.check_writable()?;
+        if name == DEFAULT_NAMESPACE {
+            return Err(DbError::Io(Error::new(
+                ErrorKind::InvalidInput,
+                "The default namespace can't be dropped.",
+            )));
+        }
+        let Some(keys) = self.namespaces.get(name) else {
+            return Err(DbError::NamespaceNotFound(name.to_string()));
+        };
+        let now = now();
+        let count = keys.values().filter(|entry| !entry.is_expired(now)).count();
+
+        self.write(vec![Record::DropNamespace(name.to_string())])?;
+        if self.namespace == name {
+            self.namespace = DEFAULT_NAMESPACE.to_string();
+        }
+
+        Ok(count)
     }
 
-    // Insert a new key/value pair into the database.
-    // Replaces existing entry if 'replace_existing' is true.
+    // Key/value pairs in the selected namespace.
+    fn keys(&self) -> &BTreeMap<String, Entry> {
+        &self.namespaces[&self.namespace]
+    }
+
+    /// The key/value pair stored for a key in the selected namespace.
+    /// Expired entries are treated as if they had been removed.
+    pub fn get(&self, key: &str) -> Option<(&String, &[u8])> {
+        let now = now();
+        self.keys()
+            .get_key_value(key)
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(k, entry)| (k, entry.value.as_slice()))
+    }
+
+    /// Version of the value stored for a key, for use with
+    /// [`Precondition::Version`].
+    pub fn version(&self, key: &str) -> Option<u64> {
+        self.entry(key).map(|entry| entry.version)
+    }
+
+    /// Earlier values of a key, oldest first. Returns None if the key doesn't
+    /// exist. Removing a key discards its history.
+    pub fn history(&self, key: &str) -> Option<&[Revision]> {
+        self.entry(key).map(|entry| entry.history.as_slice())
+    }
+
+    /// Set a key back to one of its earlier values, or to the value before
+    /// the current one if no version is given. The rollback is itself a new
+    /// version, so it can be rolled back too.
+    /// Returns the new version of the key.
+    pub fn rollback(&mut self, key: &str, version: Option<u64>) -> Result<u64, DbError> {
+        self.check_writable()?;
+        let Some(current) = self.entry(key) else {
+            return Err(DbError::NotFound(key.to_string()));
+        };
+        let revision = match version {
+            Some(version) => current.history.iter().find(|r| r.version == version),
+            None => current.history.last(),
+        };
+        let Some(revision) = revision else {
+            return Err(DbError::VersionNotFound {
+                key: key.to_string(),
+                version,
+            });
+        };
+
+        let entry = Entry::new(revision.value.clone());
+        self.write_entry(key.to_string(), entry)
+    }
+
+    // The entry for a key, unless it has expired.
+    fn entry(&self, key: &str) -> Option<&Entry> {
+        self.keys()
+            .get(key)
+            .filter(|entry| !entry.is_expired(now()))
+    }
+
+    /// Iterate over all key/value pairs in the database, in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &[u8])> {
+        let now = now();
+        self.keys()
+            .iter()
+            .filter(move |(_, entry)| !entry.is_expired(now))
+            .map(|(k, entry)| (k, entry.value.as_slice()))
+    }
+
+    /// Iterate over the key/value pairs whose keys fall in a range, in key order.
+    /// Like [`BTreeMap::range`], panics if the start of the range is after its end.
+    pub fn range<'a, R>(&self, range: R) -> impl Iterator<Item = (&String, &[u8])>
+    where
+        R: RangeBounds<&'a str>,
+    {
+        let bounds: (Bound<&str>, Bound<&str>) = (
+            range.start_bound().map(|key| *key),
+            range.end_bound().map(|key| *key),
+        );
+        let now = now();
+        self.keys()
+            .range::<str, _>(bounds)
+            .filter(move |(_, entry)| !entry.is_expired(now))
+            .map(|(k, entry)| (k, entry.value.as_slice()))
+    }
+
+    /// Insert a new key/value pair into the database.
+    /// Replaces existing entry if `replace_existing` is true.
     pub fn insert(
         &mut self,
         key: String,
-        value: String,
+        value: Vec<u8>,
+        replace_existing: bool,
+    ) -> Result<bool, DbError> {
+        self.put(key, Entry::new(value), replace_existing)
+    }
+
+    /// Like [`Database::insert`], but the entry expires once `ttl` has passed.
+    pub fn insert_with_ttl(
+        &mut self,
+        key: String,
+        value: Vec<u8>,
         replace_existing: bool,
-    ) -> std::io::Result<bool> {
-        if (self.map.contains_key(&key)) && (!replace_existing) {
-            return Err(Error::new(
-                ErrorKind::AlreadyExists,
-                format!("{} already exists in database.", key),
-            ));
+        ttl: Duration,
+    ) -> Result<bool, DbError> {
+        self.put(key, Entry::expiring(value, ttl), replace_existing)
+    }
+
+    /// Make a key expire once `ttl` has passed, keeping its value. Like any
+    /// other change to a key, this makes a new version of it.
+    /// Returns false if the key doesn't exist.
+    pub fn expire(&mut self, key: &str, ttl: Duration) -> Result<bool, DbError> {
+        self.check_writable()?;
+        let Some(current) = self.entry(key) else {
+            return Ok(false);
+        };
+        let entry = Entry::expiring(current.value.clone(), ttl);
+        self.write_entry(key.to_string(), entry)?;
+
+        Ok(true)
+    }
+
+    /// Time left before a key expires. Returns None if the key doesn't exist
+    /// or never expires.
+    pub fn ttl(&self, key: &str) -> Option<Duration> {
+        let expires_at = self.entry(key)?.expires_at?;
+        Some(Duration::from_millis(expires_at.saturating_sub(now())))
+    }
+
+    /// Check a condition against the current state of a key, failing with
+    /// [`DbError::PreconditionFailed`] if it doesn't hold.
+    pub fn check(&self, key: &str, expected: &Precondition) -> Result<(), DbError> {
+        expected.check(key, self.entry(key))
+    }
+
+    /// Set a key only if `expected` holds for its current entry, failing with
+    /// [`DbError::PreconditionFailed`] otherwise. The entry expires once `ttl`
+    /// has passed, if given.
+    /// Returns the new version of the key.
+    pub fn compare_and_swap(
+        &mut self,
+        key: String,
+        value: Vec<u8>,
+        expected: &Precondition,
+        ttl: Option<Duration>,
+    ) -> Result<u64, DbError> {
+        self.check_writable()?;
+        expected.check(&key, self.entry(&key))?;
+        let entry = match ttl {
+            Some(ttl) => Entry::expiring(value, ttl),
+            None => Entry::new(value),
+        };
+        self.write_entry(key, entry)
+    }
+
+    fn put(&mut self, key: String, entry: Entry, replace_existing: bool) -> Result<bool, DbError> {
+        self.check_writable()?;
+        if self.get(&key).is_some() && !replace_existing {
+            return Err(DbError::AlreadyExists(key));
         }
-        self.map.insert(key, value);
+        self.write_entry(key, entry)?;
 
         Ok(true)
     }
 
-    // Remove an entry from the database.
-    pub fn remove(&mut self, key: &str) -> Option<(String, String)> {
-        self.map.remove_entry(key)
+    // Store an entry as the next version of its key.
+    fn write_entry(&mut self, key: String, entry: Entry) -> Result<u64, DbError> {
+        let entry = entry.replacing(self.entry(&key));
+        let version = entry.version;
+        self.write(vec![Record::Set(self.namespace.clone(), key, entry)])?;
+
+        Ok(version)
     }
 
-    // Initialize a new empty key/value database.
-    pub fn init(&mut self) -> std::io::Result<()> {
-        self.map.clear(); // Clear the HashMap entries
-        self.flush()?; // Flush the empty HashMap to disk
+    /// Insert many key/value pairs into the database with a single write.
+    /// Replaces existing entries if `replace_existing` is true; otherwise
+    /// nothing is inserted if any of the keys already exist.
+    /// Returns the number of pairs inserted.
+    pub fn insert_all(
+        &mut self,
+        pairs: Vec<(String, Vec<u8>)>,
+        replace_existing: bool,
+    ) -> Result<usize, DbError> {
+        self.check_writable()?;
+        if !replace_existing
+            && let Some((key, _)) = pairs.iter().find(|(k, _)| self.get(k).is_some())
+        {
+            return Err(DbError::AlreadyExists(key.clone()));
+        }
 
-        Ok(())
+        if pairs.is_empty() {
+            return Ok(0);
+        }
+
+        // Like a transaction, so a crash part way through writing the records
+        // imports none of them.
+        let count = pairs.len();
+        let mut records = vec![Record::Begin];
+        records.extend(pairs.into_iter().map(|(key, value)| {
+            let entry = Entry::new(value).replacing(self.entry(&key));
+            Record::Set(self.namespace.clone(), key, entry)
+        }));
+        records.push(Record::Commit);
+        self.write(records)?;
+
+        Ok(count)
+    }
+
+    /// Remove an entry from the database.
+    pub fn remove(&mut self, key: &str) -> Result<Option<(String, Vec<u8>)>, DbError> {
+        self.check_writable()?;
+        let Some((key, value)) = self.get(key) else {
+            return Ok(None);
+        };
+        let removed = (key.clone(), value.to_vec());
+        self.write(vec![Record::Remove(self.namespace.clone(), key.clone())])?;
+
+        Ok(Some(removed))
+    }
+
+    /// Run `f` against a transaction and, if it succeeds, apply all of the
+    /// changes it made at once. If it fails, nothing is changed.
+    /// The changes are written to the write-ahead log between a begin and a
+    /// commit record, so a crash part way through writing them loses them all.
+    pub fn transaction<T, F>(&mut self, f: F) -> Result<T, DbError>
+    where
+        F: FnOnce(&mut Transaction) -> Result<T, DbError>,
+    {
+        self.check_writable()?;
+        let mut tx = Transaction::new(self);
+        let result = f(&mut tx)?;
+        let changes = tx.into_changes();
+        if changes.is_empty() {
+            return Ok(result);
+        }
+
+        let mut records = vec![Record::Begin];
+        records.extend(
+            changes
+                .into_iter()
+                .map(|(key, change)| Record::from_change(self.namespace.clone(), key, change)),
+        );
+        records.push(Record::Commit);
+        self.write(records)?;
+
+        Ok(result)
+    }
+
+    /// Remove the expired entries from the selected namespace, leaving other
+    /// namespaces as they are. Returns the keys that were removed.
+    pub fn purge(&mut self) -> Result<Vec<String>, DbError> {
+        self.check_writable()?;
+        let now = now();
+        let expired: Vec<String> = self
+            .keys()
+            .iter()
+            .filter(|(_, entry)| entry.is_expired(now))
+            .map(|(k, _)| k.clone())
+            .collect();
+
+        let records = expired
+            .iter()
+            .map(|key| Record::Remove(self.namespace.clone(), key.clone()))
+            .collect();
+        self.write(records)?;
+
+        Ok(expired)
     }
 
-    // Persist the key/value database to disk.
-    fn flush(&self) -> std::io::Result<()> {
-        let mut options = OpenOptions::new();
-        let mut file = options.write(true).truncate(true).open(&self.db_filename)?;
+    /// Initialize a new empty key/value database.
+    pub fn init(&mut self) -> Result<(), DbError> {
+        self.check_writable()?;
+        // Clear the map entries and any namespaces
+        self.namespaces = new_namespaces();
+        self.namespace = DEFAULT_NAMESPACE.to_string();
+        self.backend.write_all(&self.namespaces) // Write the empty map to disk
+    }
 
-        for (k, v) in &self.map {
-            let line = format!("{}\t{}\n", k, v);
-            let _bytes_written = file.write(line.as_bytes())?;
+    /// Whether changes have been made that are only in the write-ahead log
+    /// and not yet in the database file. Only the TSV backend keeps a log,
+    /// so a database stored with any other is never dirty.
+    pub fn is_dirty(&self) -> bool {
+        self.backend.is_dirty()
+    }
+
+    /// Write any changes that are only in the write-ahead log into the
+    /// database file and empty the log.
+    /// Every change is durable as soon as it's made, since it's written to
+    /// the log first, so committing is never required. Nothing is written
+    /// when a database is dropped, so this is the way to compact the log
+    /// and find out whether that failed. Does nothing unless the database
+    /// is dirty.
+    pub fn commit(&mut self) -> Result<(), DbError> {
+        if self.is_dirty() {
+            self.backend.write_all(&self.namespaces)?;
         }
 
         Ok(())
     }
+
+    // Only a database that is locked exclusively may be modified.
+    fn check_writable(&self) -> Result<(), DbError> {
+        match self.lock {
+            Lock::Exclusive => Ok(()),
+            Lock::Shared => Err(DbError::Io(read_only_error())),
+        }
+    }
+
+    // Durably record changes with the backend, then apply them to the
+    // database in memory.
+    fn write(&mut self, records: Vec<Record>) -> Result<(), DbError> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        self.backend.append(&records, &self.namespaces)?;
+        for record in records {
+            record.apply(&mut self.namespaces);
+        }
+        self.backend.applied(&self.namespaces)
+    }
+}
+
+// Current Unix time in milliseconds.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+// The error for writing to a database that was opened for reading only.
+fn read_only_error() -> Error {
+    Error::new(
+        ErrorKind::PermissionDenied,
+        "Database was opened for reading only.",
+    )
+}
+
+// Filename of the file that is locked to guard a database file. The database
+// file itself can't be locked since it is replaced whenever it is rewritten.
+fn lock_filename(path: &str) -> String {
+    format!("{}.lock", path)
+}
+
+// Lock a database file against other processes. Returns None where a reader
+// can go without a lock.
+fn acquire_lock(path: &str, lock: Lock, wait: bool) -> Result<Option<File>, DbError> {
+    let lock_path = lock_filename(path);
+    let file = match lock {
+        // A reader never creates the lock file. Without one, no writer has
+        // opened the database yet, and one that starts now only ever replaces
+        // the database file as a whole or appends whole records to the log,
+        // so the database is read without a lock.
+        Lock::Shared => match File::open(&lock_path) {
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            result => result?,
+        },
+        Lock::Exclusive => create_lock_file(&lock_path)?,
+    };
+
+    if wait {
+        match lock {
+            Lock::Shared => file.lock_shared()?,
+            Lock::Exclusive => file.lock()?,
+        }
+    } else {
+        let result = match lock {
+            Lock::Shared => file.try_lock_shared(),
+            Lock::Exclusive => file.try_lock(),
+        };
+        match result {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Err(DbError::Locked(path.to_string())),
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+    }
+
+    Ok(Some(file))
+}
+
+fn create_lock_file(path: &str) -> std::io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
+
+// Filename of the temp file a database file is written to before it is renamed.
+fn tmp_filename(path: &str) -> String {
+    format!("{}.tmp", path)
+}
+
+// Persist a rename by syncing the directory that holds the file.
+#[cfg(unix)]
+fn sync_parent_dir(path: &str) -> std::io::Result<()> {
+    let parent = match std::path::Path::new(path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => std::path::Path::new("."),
+    };
+    File::open(parent)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &str) -> std::io::Result<()> {
+    Ok(())
+}
+
+// Namespaces of a database with no keys, which only has the default one.
+fn new_namespaces() -> Namespaces {
+    Namespaces::from([(DEFAULT_NAMESPACE.to_string(), BTreeMap::new())])
+}
+
+// Write a file as a whole. The contents are written to a sibling temp file
+// which is then renamed over the file, so a reader never sees a partially
+// written file.
+fn write_atomically(path: &str, contents: &[u8]) -> std::io::Result<()> {
+    let tmp_filename = tmp_filename(path);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&tmp_filename)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+
+    fs::rename(&tmp_filename, path)?;
+    sync_parent_dir(path)
 }
@@ -0,0 +1,262 @@
+// A backend storing the database in an SQLite file, with a row for each key
+// and for each of its earlier values. The records of each change are carried
+// out in a transaction of their own, so a change is durable as soon as it's
+// made without the file ever being rewritten as a whole.
+
+use super::backend::Backend;
+use super::{
+    DEFAULT_NAMESPACE, DbError, Entry, HISTORY_LIMIT, Lock, Namespaces, Record, Revision,
+    new_namespaces, now, read_only_error,
+};
+use rusqlite::{Connection, OpenFlags, Transaction, params};
+use std::io::Error;
+use std::path::Path;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS namespaces (
+        name TEXT PRIMARY KEY
+    );
+    CREATE TABLE IF NOT EXISTS entries (
+        namespace TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value BLOB NOT NULL,
+        version INTEGER NOT NULL,
+        expires INTEGER,
+        updated INTEGER,
+        PRIMARY KEY (namespace, key)
+    );
+    CREATE TABLE IF NOT EXISTS history (
+        namespace TEXT NOT NULL,
+        key TEXT NOT NULL,
+        version INTEGER NOT NULL,
+        value BLOB NOT NULL,
+        replaced INTEGER NOT NULL,
+        PRIMARY KEY (namespace, key, version)
+    );
+";
+
+pub(super) struct SqliteFile {
+    connection: Option<Connection>, // None for a reader when the file doesn't exist
+}
+
+impl SqliteFile {
+    // Read the database from an SQLite file. A writer creates the file if it
+    // doesn't exist, while a reader reads it as empty.
+    pub(super) fn open(path: &str, lock: Lock) -> Result<(SqliteFile, Namespaces), DbError> {
+        let connection = match lock {
+            Lock::Exclusive => {
+                let connection = Connection::open(path).map_err(sql_error)?;
+                connection.execute_batch(SCHEMA).map_err(sql_error)?;
+                Some(connection)
+            }
+            Lock::Shared if Path::new(path).exists() => Some(
+                Connection::open_with_flags(
+                    path,
+                    OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+                )
+                .map_err(sql_error)?,
+            ),
+            Lock::Shared => None,
+        };
+
+        let namespaces = match &connection {
+            Some(connection) => read(connection).map_err(sql_error)?,
+            None => new_namespaces(),
+        };
+        Ok((SqliteFile { connection }, namespaces))
+    }
+
+    // Run 'f' in a transaction, committing it if it succeeds.
+    fn transaction<F>(&mut self, f: F) -> Result<(), DbError>
+    where
+        F: FnOnce(&Transaction) -> rusqlite::Result<()>,
+    {
+        let Some(connection) = self.connection.as_mut() else {
+            return Err(DbError::Io(read_only_error()));
+        };
+        let tx = connection.transaction().map_err(sql_error)?;
+        f(&tx).and_then(|()| tx.commit()).map_err(sql_error)
+    }
+}
+
+impl Backend for SqliteFile {
+    fn append(&mut self, records: &[Record], _namespaces: &Namespaces) -> Result<(), DbError> {
+        self.transaction(|tx| {
+            for record in records {
+                apply(tx, record)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn applied(&mut self, _namespaces: &Namespaces) -> Result<(), DbError> {
+        Ok(())
+    }
+
+    fn write_all(&mut self, namespaces: &Namespaces) -> Result<(), DbError> {
+        self.transaction(|tx| {
+            tx.execute_batch("DELETE FROM namespaces; DELETE FROM entries; DELETE FROM history;")?;
+            let now = now();
+            for (name, keys) in namespaces {
+                tx.execute("INSERT INTO namespaces (name) VALUES (?1)", [name])?;
+                for (key, entry) in keys.iter().filter(|(_, e)| !e.is_expired(now)) {
+                    insert_entry(tx, name, key, entry)?;
+                    for revision in &entry.history {
+                        tx.execute(
+                            "INSERT INTO history (namespace, key, version, value, replaced)
+                             VALUES (?1, ?2, ?3, ?4, ?5)",
+                            params![
+                                name,
+                                key,
+                                integer(revision.version),
+                                revision.value,
+                                integer(revision.replaced_at)
+                            ],
+                        )?;
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn is_dirty(&self) -> bool {
+        false
+    }
+}
+
+// Read every namespace, along with its keys and their earlier values.
+fn read(connection: &Connection) -> rusqlite::Result<Namespaces> {
+    let mut namespaces = new_namespaces();
+    let mut names = connection.prepare("SELECT name FROM namespaces")?;
+    for name in names.query_map([], |row| row.get::<_, String>(0))? {
+        namespaces.entry(name?).or_default();
+    }
+
+    let mut entries = connection
+        .prepare("SELECT namespace, key, value, version, expires, updated FROM entries")?;
+    let rows = entries.query_map([], |row| {
+        let entry = Entry {
+            value: row.get(2)?,
+            version: row.get(3)?,
+            expires_at: row.get(4)?,
+            updated_at: row.get(5)?,
+            history: Vec::new(),
+        };
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, entry))
+    })?;
+    for row in rows {
+        let (namespace, key, entry) = row?;
+        namespaces.entry(namespace).or_default().insert(key, entry);
+    }
+
+    let mut history = connection.prepare(
+        "SELECT namespace, key, version, value, replaced FROM history
+         ORDER BY namespace, key, version",
+    )?;
+    let rows = history.query_map([], |row| {
+        let revision = Revision {
+            version: row.get(2)?,
+            value: row.get(3)?,
+            replaced_at: row.get(4)?,
+        };
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, revision))
+    })?;
+    for row in rows {
+        let (namespace, key, revision) = row?;
+        if let Some(entry) = namespaces
+            .get_mut(&namespace)
+            .and_then(|keys| keys.get_mut(&key))
+        {
+            entry.history.push(revision);
+        }
+    }
+
+    Ok(namespaces)
+}
+
+// Make the change a record describes, the same way 'Record::apply' makes it
+// in memory.
+fn apply(tx: &Transaction, record: &Record) -> rusqlite::Result<()> {
+    match record {
+        Record::Set(namespace, key, entry) => {
+            if entry.version > 1 {
+                // Move the version being replaced into the key's history.
+                tx.execute(
+                    "INSERT OR REPLACE INTO history (namespace, key, version, value, replaced)
+                     SELECT namespace, key, version, value, ?3 FROM entries
+                     WHERE namespace = ?1 AND key = ?2",
+                    params![namespace, key, integer(entry.updated_at.unwrap_or(0))],
+                )?;
+                tx.execute(
+                    "DELETE FROM history WHERE namespace = ?1 AND key = ?2 AND version NOT IN
+                     (SELECT version FROM history WHERE namespace = ?1 AND key = ?2
+                      ORDER BY version DESC LIMIT ?3)",
+                    params![namespace, key, HISTORY_LIMIT],
+                )?;
+            } else {
+                delete_history(tx, namespace, key)?;
+            }
+            insert_entry(tx, namespace, key, entry)?;
+        }
+        Record::Remove(namespace, key) => {
+            tx.execute(
+                "DELETE FROM entries WHERE namespace = ?1 AND key = ?2",
+                [namespace, key],
+            )?;
+            delete_history(tx, namespace, key)?;
+        }
+        Record::CreateNamespace(name) => {
+            tx.execute(
+                "INSERT OR IGNORE INTO namespaces (name) VALUES (?1)",
+                [name],
+            )?;
+        }
+        Record::DropNamespace(name) if name != DEFAULT_NAMESPACE => {
+            tx.execute("DELETE FROM namespaces WHERE name = ?1", [name])?;
+            tx.execute("DELETE FROM entries WHERE namespace = ?1", [name])?;
+            tx.execute("DELETE FROM history WHERE namespace = ?1", [name])?;
+        }
+        Record::DropNamespace(_) | Record::Begin | Record::Commit => {}
+    }
+    Ok(())
+}
+
+fn insert_entry(
+    tx: &Transaction,
+    namespace: &str,
+    key: &str,
+    entry: &Entry,
+) -> rusqlite::Result<()> {
+    tx.execute(
+        "INSERT OR REPLACE INTO entries (namespace, key, value, version, expires, updated)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            namespace,
+            key,
+            entry.value,
+            integer(entry.version),
+            entry.expires_at.map(integer),
+            entry.updated_at.map(integer)
+        ],
+    )?;
+    Ok(())
+}
+
+fn delete_history(tx: &Transaction, namespace: &str, key: &str) -> rusqlite::Result<()> {
+    tx.execute(
+        "DELETE FROM history WHERE namespace = ?1 AND key = ?2",
+        [namespace, key],
+    )?;
+    Ok(())
+}
+
+// SQLite only stores signed integers, so a time too far off to fit, like that
+// of a TTL that saturated, is stored as the latest one that does.
+fn integer(n: u64) -> i64 {
+    i64::try_from(n).unwrap_or(i64::MAX)
+}
+
+fn sql_error(e: rusqlite::Error) -> DbError {
+    DbError::Io(Error::other(e))
+}
@@ -0,0 +1,103 @@
+use super::{Database, DbError};
+use std::collections::BTreeMap;
+
+/// The basic operations of a key/value store, so code that reads and writes
+/// keys can be given a [`Database`] on disk or a [`MemoryStore`] in tests.
+pub trait Store {
+    /// The key/value pair stored for a key.
+    fn get(&self, key: &str) -> Option<(&String, &[u8])>;
+
+    /// Insert a key/value pair. Fails with [`DbError::AlreadyExists`] if the
+    /// key is already set, unless `replace_existing` is true.
+    fn insert(
+        &mut self,
+        key: String,
+        value: Vec<u8>,
+        replace_existing: bool,
+    ) -> Result<(), DbError>;
+
+    /// Remove a key, returning the pair that was removed if it was set.
+    fn remove(&mut self, key: &str) -> Result<Option<(String, Vec<u8>)>, DbError>;
+
+    /// Iterate over all key/value pairs, in key order.
+    fn iter(&self) -> Box<dyn Iterator<Item = (&String, &[u8])> + '_>;
+
+    /// Make sure every change so far has been written out.
+    fn flush(&mut self) -> Result<(), DbError>;
+}
+
+impl Store for Database {
+    fn get(&self, key: &str) -> Option<(&String, &[u8])> {
+        Database::get(self, key)
+    }
+
+    fn insert(
+        &mut self,
+        key: String,
+        value: Vec<u8>,
+        replace_existing: bool,
+    ) -> Result<(), DbError> {
+        Database::insert(self, key, value, replace_existing)?;
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> Result<Option<(String, Vec<u8>)>, DbError> {
+        Database::remove(self, key)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&String, &[u8])> + '_> {
+        Box::new(Database::iter(self))
+    }
+
+    /// Same as [`Database::commit`].
+    fn flush(&mut self) -> Result<(), DbError> {
+        self.commit()
+    }
+}
+
+/// A store that keeps key/value pairs in memory only, for tests and other
+/// uses that don't need them to outlive the process.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryStore {
+    entries: BTreeMap<String, Vec<u8>>,
+}
+
+impl MemoryStore {
+    /// Create an empty store.
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl Store for MemoryStore {
+    fn get(&self, key: &str) -> Option<(&String, &[u8])> {
+        self.entries
+            .get_key_value(key)
+            .map(|(k, value)| (k, value.as_slice()))
+    }
+
+    fn insert(
+        &mut self,
+        key: String,
+        value: Vec<u8>,
+        replace_existing: bool,
+    ) -> Result<(), DbError> {
+        if self.entries.contains_key(&key) && !replace_existing {
+            return Err(DbError::AlreadyExists(key));
+        }
+        self.entries.insert(key, value);
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &str) -> Result<Option<(String, Vec<u8>)>, DbError> {
+        Ok(self.entries.remove_entry(key))
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&String, &[u8])> + '_> {
+        Box::new(self.entries.iter().map(|(k, value)| (k, value.as_slice())))
+    }
+
+    fn flush(&mut self) -> Result<(), DbError> {
+        Ok(())
+    }
+}
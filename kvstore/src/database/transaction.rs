@@ -0,0 +1,70 @@
+use super::{Database, DbError, Entry, Precondition};
+use std::collections::BTreeMap;
+
+/// Changes to a database that are applied all at once by [`Database::transaction`].
+pub struct Transaction<'a> {
+    db: &'a Database,
+    changes: BTreeMap<String, Option<Entry>>, // New entries, or None for removed keys
+}
+
+impl<'a> Transaction<'a> {
+    pub(super) fn new(db: &'a Database) -> Transaction<'a> {
+        Transaction {
+            db,
+            changes: BTreeMap::new(),
+        }
+    }
+
+    pub(super) fn into_changes(self) -> BTreeMap<String, Option<Entry>> {
+        self.changes
+    }
+
+    /// Like [`Database::get`], but sees the changes made in the transaction so far.
+    pub fn get(&self, key: &str) -> Option<(&String, &[u8])> {
+        match self.changes.get_key_value(key) {
+            Some((k, Some(entry))) => Some((k, entry.value.as_slice())),
+            Some((_, None)) => None,
+            None => self.db.get(key),
+        }
+    }
+
+    /// Check a condition against the state of a key, including the changes
+    /// made in the transaction so far.
+    pub fn check(&self, key: &str, expected: &Precondition) -> Result<(), DbError> {
+        expected.check(key, self.entry(key))
+    }
+
+    // The entry a key will have once the transaction is committed.
+    fn entry(&self, key: &str) -> Option<&Entry> {
+        match self.changes.get(key) {
+            Some(change) => change.as_ref(),
+            None => self.db.entry(key),
+        }
+    }
+
+    /// Insert a new key/value pair when the transaction is committed.
+    /// Replaces existing entry if `replace_existing` is true.
+    pub fn insert(
+        &mut self,
+        key: String,
+        value: Vec<u8>,
+        replace_existing: bool,
+    ) -> Result<bool, DbError> {
+        if self.get(&key).is_some() && !replace_existing {
+            return Err(DbError::AlreadyExists(key));
+        }
+        let entry = Entry::new(value).replacing(self.entry(&key));
+        self.changes.insert(key, Some(entry));
+
+        Ok(true)
+    }
+
+    /// Remove an entry when the transaction is committed.
+    pub fn remove(&mut self, key: &str) -> Option<(String, Vec<u8>)> {
+        let (_, value) = self.get(key)?;
+        let removed = (key.to_string(), value.to_vec());
+        self.changes.insert(key.to_string(), None);
+
+        Some(removed)
+    }
+}
@@ -0,0 +1,602 @@
+// The default backend: a TSV file holding the whole database, along with a
+// write-ahead log that each change is appended to before it's applied. The
+// log is compacted into the file once it grows large, or on 'commit'.
+
+use super::backend::Backend;
+use super::crypto::{self, Key};
+use super::format::{self, Version};
+use super::{
+    DEFAULT_NAMESPACE, DbError, Entry, Lock, Namespaces, Record, Revision, new_namespaces, now,
+    read_only_error, write_atomically,
+};
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+
+// Number of records the write-ahead log may hold before it is compacted
+// into the database file.
+const COMPACT_THRESHOLD: usize = 1000;
+
+// A database file and its write-ahead log.
+pub(super) struct TsvFile {
+    path: String,       // Filename that key/value database is persisted to
+    log: Option<File>,  // Append-only write-ahead log, unless opened for reading only
+    log_records: usize, // Number of records currently in the write-ahead log
+    key: Option<Key>,   // Key the files are encrypted with, if they are
+}
+
+// A line that couldn't be parsed while reading the database from disk.
+struct BadLine {
+    path: String,
+    line: usize,
+    text: String,
+    reason: String,
+}
+
+impl TsvFile {
+    // Read a database file into memory and replay the write-ahead log on top
+    // of it, creating the files if they don't exist unless only reading.
+    // With 'repair', lines that can't be parsed are moved to a quarantine
+    // file instead of failing. Returns the file along with the namespaces read
+    // from it and the number of lines quarantined.
+    pub(super) fn open(
+        path: &str,
+        lock: Lock,
+        repair: bool,
+        passphrase: Option<&[u8]>,
+    ) -> Result<(TsvFile, Namespaces, usize), DbError> {
+        // Read the contents of the key/value database file as a single long
+        // String, creating the file if it doesn't exist.
+        let (_, mut contents) = read_file(path, lock)?;
+
+        // Decrypt an encrypted database. One that isn't encrypted yet is
+        // encrypted by the first writer given a passphrase.
+        let encrypted = crypto::is_encrypted(&contents);
+        let key = match passphrase {
+            _ if encrypted => {
+                let (key, sealed) = file_key(path, &contents, passphrase)?;
+                contents = key
+                    .open(sealed.trim_end_matches('\n'), crypto::DATABASE_FILE)
+                    .map_err(|_| DbError::WrongKey(path.to_string()))?;
+                Some(key)
+            }
+            Some(passphrase) if lock == Lock::Exclusive => Some(Key::generate(passphrase)?),
+            _ => None,
+        };
+
+        // Populate a map in memory of the file's contents. The keys of the
+        // default namespace come first, followed by a section for each of
+        // the other namespaces.
+        let mut bad_lines = Vec::new();
+        let (version, records) = format::split_header(&contents);
+        let first_line = first_record_line(version);
+        let mut namespaces = new_namespaces();
+        let mut namespace = DEFAULT_NAMESPACE.to_string();
+        for (i, line) in records.lines().enumerate() {
+            let parsed = match parse_namespace(line, version) {
+                Some(Ok(name)) => {
+                    namespaces.entry(name.clone()).or_default();
+                    namespace = name;
+                    Ok(())
+                }
+                Some(Err(reason)) => Err(reason),
+                None => parse_entry(line, version).map(|(key, entry)| {
+                    namespaces
+                        .entry(namespace.clone())
+                        .or_default()
+                        .insert(key, entry);
+                }),
+            };
+            if let Err(reason) = parsed {
+                // Keep the contents of an encrypted database encrypted, even
+                // once they are quarantined.
+                let text = match &key {
+                    Some(key) if encrypted => key.seal(line, crypto::DATABASE_FILE)?,
+                    _ => line.to_string(),
+                };
+                bad_lines.push(BadLine {
+                    path: path.to_string(),
+                    line: first_line + i,
+                    text,
+                    reason,
+                });
+            }
+        }
+
+        // Likewise read the write-ahead log next to the database file.
+        let log_path = log_filename(path);
+        let (log, log_contents) = read_file(&log_path, lock)?;
+
+        // The records of an encrypted log are opened one at a time, normally
+        // with the same key as the database file.
+        let log_key = match &key {
+            Some(key) if log_contents.starts_with(&key.header()) => Some(key.clone()),
+            _ if crypto::is_encrypted(&log_contents) => {
+                Some(file_key(&log_path, &log_contents, passphrase)?.0)
+            }
+            _ => None,
+        };
+
+        // Replay the log. A record is only complete once its trailing newline
+        // has been written, and the records of a transaction only once its
+        // commit record has been written, so anything left incomplete by a
+        // crash is ignored.
+        let (log_version, records) = match log_key {
+            Some(_) => (
+                Version::V2,
+                log_contents
+                    .split_once('\n')
+                    .map_or("", |(_, records)| records),
+            ),
+            None => format::split_header(&log_contents),
+        };
+        let first_line = first_record_line(log_version);
+        let mut log_records = 0;
+        let mut log_len = log_contents.len() - records.len();
+        let mut offset = log_len;
+        let mut transaction: Option<Vec<Record>> = None;
+        for (i, line) in records.split_inclusive('\n').enumerate() {
+            let Some(line) = line.strip_suffix('\n') else {
+                break;
+            };
+            offset += line.len() + 1;
+            let mut bad_line = |reason| {
+                bad_lines.push(BadLine {
+                    path: log_path.clone(),
+                    line: first_line + i,
+                    text: line.to_string(),
+                    reason,
+                })
+            };
+
+            let record = match &log_key {
+                Some(key) => key.open(line, crypto::LOG_FILE),
+                None => Ok(line.to_string()),
+            };
+            match record.and_then(|record| parse_record(&record, log_version)) {
+                Ok(Record::Begin) if transaction.is_none() => transaction = Some(Vec::new()),
+                Ok(Record::Commit) if transaction.is_some() => {
+                    for record in transaction.take().into_iter().flatten() {
+                        record.apply(&mut namespaces);
+                    }
+                }
+                Ok(Record::Begin | Record::Commit) => {
+                    bad_line("unexpected transaction record".to_string())
+                }
+                Ok(record) => match &mut transaction {
+                    Some(pending) => pending.push(record),
+                    None => record.apply(&mut namespaces),
+                },
+                Err(reason) => bad_line(reason),
+            }
+
+            // Everything up to here has been applied, unless it's part of a
+            // transaction that hasn't been committed yet.
+            if transaction.is_none() {
+                log_len = offset;
+                log_records = i + 1;
+            }
+        }
+
+        if !repair && !bad_lines.is_empty() {
+            let bad = bad_lines.swap_remove(0);
+            return Err(DbError::Corrupt {
+                path: bad.path,
+                line: bad.line,
+                reason: bad.reason,
+            });
+        }
+
+        // Readers leave the files as they are; the next writer tidies them up.
+        let Some(mut log) = log.filter(|_| lock == Lock::Exclusive) else {
+            let file = TsvFile {
+                path: path.to_string(),
+                log: None,
+                log_records,
+                key,
+            };
+            return Ok((file, namespaces, 0));
+        };
+
+        let log_header = match &key {
+            Some(key) => key.header(),
+            None => format::HEADER.to_string(),
+        };
+        if log_records == 0 && log_contents != log_header {
+            // Start every new log with the format header.
+            log.set_len(0)?;
+            log.write_all(log_header.as_bytes())?;
+        } else if log_len < log_contents.len() {
+            // Drop any partial record so new records aren't appended onto it.
+            log.set_len(log_len as u64)?;
+        }
+
+        let mut file = TsvFile {
+            path: path.to_string(),
+            log: Some(log),
+            log_records,
+            key,
+        };
+
+        // Move corrupt lines out of the way so they can be inspected and
+        // fixed by hand, then rewrite the database without them.
+        let quarantined = bad_lines.len();
+        if quarantined > 0 {
+            let mut quarantine = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(quarantine_filename(path))?;
+            for bad in bad_lines {
+                let line = format!("{}:{}\t{}\n", bad.path, bad.line, bad.text);
+                quarantine.write_all(line.as_bytes())?;
+            }
+            quarantine.sync_data()?;
+            file.compact(&namespaces)?;
+        }
+
+        // Compact a large log, or one written in the version 1 format, so
+        // new records are never mixed with records in an older format.
+        if file.log_records > 0 && log_version == Version::V1 {
+            file.compact(&namespaces)?;
+        }
+        // Likewise, encrypt a database that was given a passphrase, along with
+        // any records left in a log that isn't encrypted.
+        if file.key.is_some() && (!encrypted || file.log_records > 0 && log_key.is_none()) {
+            file.compact(&namespaces)?;
+        }
+        file.compact_if_full(&namespaces)?;
+
+        Ok((file, namespaces, quarantined))
+    }
+
+    // The write-ahead log, which is only open once the database is locked
+    // exclusively.
+    fn log(&mut self) -> std::io::Result<&mut File> {
+        self.log.as_mut().ok_or_else(read_only_error)
+    }
+
+    // Compact the write-ahead log into the database file once it grows past
+    // the threshold.
+    fn compact_if_full(&mut self, namespaces: &Namespaces) -> std::io::Result<()> {
+        if self.log_records >= COMPACT_THRESHOLD {
+            self.compact(namespaces)?;
+        }
+
+        Ok(())
+    }
+
+    // Rewrite the database file from memory and truncate the write-ahead log.
+    // The log is only truncated once the database file has been written, so
+    // a crash in between just replays records that are already applied.
+    fn compact(&mut self, namespaces: &Namespaces) -> std::io::Result<()> {
+        self.write_snapshot(namespaces)?;
+        let header = match &self.key {
+            Some(key) => key.header(),
+            None => format::HEADER.to_string(),
+        };
+        let log = self.log()?;
+        log.set_len(0)?;
+        log.write_all(header.as_bytes())?;
+        log.sync_data()?;
+        self.log_records = 0;
+
+        Ok(())
+    }
+
+    // Persist the key/value database to disk.
+    fn write_snapshot(&self, namespaces: &Namespaces) -> std::io::Result<()> {
+        let now = now();
+        let mut contents = format::HEADER.to_string();
+        // The default namespace goes first, since keys belong to it until the
+        // first namespace line.
+        let default = namespaces.get_key_value(DEFAULT_NAMESPACE);
+        let others = namespaces
+            .iter()
+            .filter(|(name, _)| *name != DEFAULT_NAMESPACE);
+        for (name, keys) in default.into_iter().chain(others) {
+            if name != DEFAULT_NAMESPACE {
+                contents.push_str(&format!("ns={}\n", format::escape(name)));
+            }
+            for (k, entry) in keys.iter().filter(|(_, e)| !e.is_expired(now)) {
+                contents.push_str(&format!("{}\t{}\n", format::escape(k), entry_fields(entry)));
+            }
+        }
+
+        // An encrypted database is sealed as a whole.
+        if let Some(key) = &self.key {
+            let sealed = key.seal(&contents, crypto::DATABASE_FILE)?;
+            contents = format!("{}{}\n", key.header(), sealed);
+        }
+        write_atomically(&self.path, contents.as_bytes())
+    }
+}
+
+impl Backend for TsvFile {
+    fn append(&mut self, records: &[Record], _namespaces: &Namespaces) -> Result<(), DbError> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let records = records.iter().map(record_line);
+        let records = match &self.key {
+            Some(key) => records
+                .map(|record| Ok(key.seal(&record, crypto::LOG_FILE)? + "\n"))
+                .collect::<std::io::Result<Vec<_>>>()?,
+            None => records.map(|record| record + "\n").collect(),
+        };
+        let log = self.log()?;
+        let len = log.metadata()?.len();
+        if let Err(e) = log
+            .write_all(records.concat().as_bytes())
+            .and_then(|()| log.sync_data())
+        {
+            // Take back whatever part of the records made it into the log, so
+            // the next records aren't joined onto it and a change that failed
+            // isn't replayed later.
+            let _ = log.set_len(len).and_then(|()| log.sync_data());
+            return Err(e.into());
+        }
+        self.log_records += records.len();
+
+        Ok(())
+    }
+
+    fn applied(&mut self, namespaces: &Namespaces) -> Result<(), DbError> {
+        Ok(self.compact_if_full(namespaces)?)
+    }
+
+    fn write_all(&mut self, namespaces: &Namespaces) -> Result<(), DbError> {
+        Ok(self.compact(namespaces)?)
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.log.is_some() && self.log_records > 0
+    }
+}
+
+// Format a record for the write-ahead log, without its trailing newline.
+fn record_line(record: &Record) -> String {
+    match record {
+        Record::Set(namespace, key, entry) => set_record(namespace, key, entry),
+        Record::Remove(namespace, key) => remove_record(namespace, key),
+        Record::CreateNamespace(name) => namespace_record("create", name),
+        Record::DropNamespace(name) => namespace_record("drop", name),
+        Record::Begin => "begin".to_string(),
+        Record::Commit => "commit".to_string(),
+    }
+}
+
+// Derive the key for an encrypted file from its header.
+fn file_key<'a>(
+    path: &str,
+    contents: &'a str,
+    passphrase: Option<&[u8]>,
+) -> Result<(Key, &'a str), DbError> {
+    let Some(passphrase) = passphrase else {
+        return Err(DbError::KeyRequired(path.to_string()));
+    };
+    Key::from_header(contents, passphrase).map_err(|reason| DbError::Corrupt {
+        path: path.to_string(),
+        line: 1,
+        reason,
+    })
+}
+
+// Parse a `key\tvalue[\tattribute...]` line from the database file.
+fn parse_entry(line: &str, version: Version) -> Result<(String, Entry), String> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    match (version, fields.as_slice()) {
+        // Version 1 files can't escape tabs, so anything past the second tab
+        // was never part of the value.
+        (Version::V1, [key, value, ..]) => {
+            Ok((key.to_string(), Entry::new(value.as_bytes().to_vec())))
+        }
+        (Version::V2, [key, value, attributes @ ..]) => Ok((
+            format::unescape(key, version)?,
+            parse_entry_fields(value, attributes, version)?,
+        )),
+        _ => Err("expected a key and a value separated by a tab".to_string()),
+    }
+}
+
+// Parse a `ns=name` line from the database file, which starts the section
+// for a namespace. Returns None for any other line.
+fn parse_namespace(line: &str, version: Version) -> Option<Result<String, String>> {
+    if version == Version::V1 || line.contains('\t') {
+        return None;
+    }
+    let name = line.strip_prefix("ns=")?;
+    Some(match format::unescape(name, version) {
+        Ok(name) if name.is_empty() => Err("empty namespace name".to_string()),
+        result => result,
+    })
+}
+
+// Parse a `set\tkey\tvalue[\tattribute...]`, `remove\tkey[\tns=name]`,
+// `create\tname`, `drop\tname`, `begin` or `commit` record from the
+// write-ahead log.
+fn parse_record(line: &str, version: Version) -> Result<Record, String> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    match fields.as_slice() {
+        ["set", key, value, attributes @ ..] => {
+            let (namespace, attributes) = split_namespace(attributes, version)?;
+            Ok(Record::Set(
+                namespace,
+                format::unescape(key, version)?,
+                parse_entry_fields(value, &attributes, version)?,
+            ))
+        }
+        ["remove", key, attributes @ ..] => {
+            let (namespace, attributes) = split_namespace(attributes, version)?;
+            if let Some(attribute) = attributes.first() {
+                return Err(format!("unknown attribute '{}'", attribute));
+            }
+            Ok(Record::Remove(namespace, format::unescape(key, version)?))
+        }
+        ["create", name] => Ok(Record::CreateNamespace(format::unescape(name, version)?)),
+        ["drop", name] => Ok(Record::DropNamespace(format::unescape(name, version)?)),
+        ["begin"] => Ok(Record::Begin),
+        ["commit"] => Ok(Record::Commit),
+        _ => Err("expected a set or remove record".to_string()),
+    }
+}
+
+// Separate the `ns=name` attribute of a log record from its other attributes.
+// Records without one belong to the default namespace.
+fn split_namespace<'a>(
+    attributes: &[&'a str],
+    version: Version,
+) -> Result<(String, Vec<&'a str>), String> {
+    let mut namespace = DEFAULT_NAMESPACE.to_string();
+    let mut others = Vec::new();
+    for attribute in attributes {
+        match attribute.strip_prefix("ns=") {
+            Some(name) => namespace = format::unescape(name, version)?,
+            None => others.push(*attribute),
+        }
+    }
+    Ok((namespace, others))
+}
+
+// Parse a value along with the `name=value` attributes written after it.
+fn parse_entry_fields(value: &str, attributes: &[&str], version: Version) -> Result<Entry, String> {
+    let mut entry = Entry::new(format::unescape_bytes(value, version)?);
+    for attribute in attributes {
+        match attribute.split_once('=') {
+            Some(("expires", millis)) => {
+                let expires_at = millis
+                    .parse()
+                    .map_err(|_| format!("invalid expiry time '{}'", millis))?;
+                entry.expires_at = Some(expires_at);
+            }
+            Some(("version", version)) => entry.version = parse_version(version)?,
+            Some(("updated", millis)) => {
+                let updated_at = millis
+                    .parse()
+                    .map_err(|_| format!("invalid update time '{}'", millis))?;
+                entry.updated_at = Some(updated_at);
+            }
+            Some(("prev", revision)) => {
+                let invalid = || format!("invalid revision '{}'", revision);
+                let mut parts = revision.splitn(3, ':');
+                let (Some(number), Some(replaced_at), Some(old_value)) =
+                    (parts.next(), parts.next(), parts.next())
+                else {
+                    return Err(invalid());
+                };
+                entry.history.push(Revision {
+                    version: parse_version(number)?,
+                    value: format::unescape_bytes(old_value, version)?,
+                    replaced_at: replaced_at.parse().map_err(|_| invalid())?,
+                });
+            }
+            _ => return Err(format!("unknown attribute '{}'", attribute)),
+        }
+    }
+    Ok(entry)
+}
+
+// Parse a key's version number, which starts at 1.
+fn parse_version(version: &str) -> Result<u64, String> {
+    version
+        .parse()
+        .ok()
+        .filter(|&version| version > 0)
+        .ok_or_else(|| format!("invalid version '{}'", version))
+}
+
+// Format a write-ahead log record that sets a key.
+fn set_record(namespace: &str, key: &str, entry: &Entry) -> String {
+    format!(
+        "set\t{}\t{}{}",
+        format::escape(key),
+        entry_fields(entry),
+        namespace_attribute(namespace)
+    )
+}
+
+// Format a write-ahead log record that removes a key.
+fn remove_record(namespace: &str, key: &str) -> String {
+    format!(
+        "remove\t{}{}",
+        format::escape(key),
+        namespace_attribute(namespace)
+    )
+}
+
+// Format a write-ahead log record that creates or drops a namespace.
+fn namespace_record(kind: &str, namespace: &str) -> String {
+    format!("{}\t{}", kind, format::escape(namespace))
+}
+
+// Format the attribute naming the namespace of a log record. Records for the
+// default namespace leave it out.
+fn namespace_attribute(namespace: &str) -> String {
+    match namespace {
+        DEFAULT_NAMESPACE => String::new(),
+        _ => format!("\tns={}", format::escape(namespace)),
+    }
+}
+
+// Format an entry's value and attributes the way 'parse_entry_fields' reads them.
+fn entry_fields(entry: &Entry) -> String {
+    let mut fields = format::escape_bytes(&entry.value);
+    if let Some(expires_at) = entry.expires_at {
+        fields.push_str(&format!("\texpires={}", expires_at));
+    }
+    // Most keys are never overwritten, so the first version is left implicit.
+    if entry.version > 1 {
+        fields.push_str(&format!("\tversion={}", entry.version));
+    }
+    if let Some(updated_at) = entry.updated_at {
+        fields.push_str(&format!("\tupdated={}", updated_at));
+    }
+    for revision in &entry.history {
+        fields.push_str(&format!(
+            "\tprev={}:{}:{}",
+            revision.version,
+            revision.replaced_at,
+            format::escape_bytes(&revision.value)
+        ));
+    }
+    fields
+}
+
+// Line number of the first record in a file, after any header.
+fn first_record_line(version: Version) -> usize {
+    match version {
+        Version::V1 => 1,
+        Version::V2 => 2,
+    }
+}
+
+// Filename of the write-ahead log that accompanies a database file.
+fn log_filename(path: &str) -> String {
+    format!("{}.log", path)
+}
+
+// Read the whole of a file. A writer opens it to append to, creating it if it
+// doesn't exist. A reader only opens it for reading and treats a missing file
+// as empty, so reading never creates or changes the database files.
+fn read_file(path: &str, lock: Lock) -> std::io::Result<(Option<File>, String)> {
+    let file = match lock {
+        Lock::Exclusive => OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path),
+        Lock::Shared => File::open(path),
+    };
+    let mut contents = String::new();
+    match file {
+        Ok(mut file) => {
+            file.read_to_string(&mut contents)?;
+            Ok((Some(file), contents))
+        }
+        Err(e) if lock == Lock::Shared && e.kind() == ErrorKind::NotFound => Ok((None, contents)),
+        Err(e) => Err(e),
+    }
+}
+
+// Filename that corrupt lines are moved to by 'Database::repair'.
+pub(crate) fn quarantine_filename(path: &str) -> String {
+    format!("{}.corrupt", path)
+}
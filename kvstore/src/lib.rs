@@ -1,8 +1,33 @@
-use clap::{Arg, Command};
-use database::Database;
-use std::io::{Error, ErrorKind};
+use clap::{Arg, ArgGroup, Command};
+use database::{DEFAULT_NAMESPACE, Precondition};
+use output::Output;
+use server::{Address, Protocol};
+use std::io::{Error, ErrorKind, Read};
+use std::ops::Bound;
+use std::path::PathBuf;
+use std::time::Duration;
 
-mod database;
+pub use database::{BackendKind, Database, DbError, Lock, MemoryStore, Store};
+
+mod batch;
+mod client;
+pub mod database;
+mod output;
+mod server;
+mod transfer;
+mod watch;
+
+pub struct Config {
+    cmd: SubCommand,
+    db_path: String,
+    backend: BackendKind,
+    namespace: String,
+    passphrase: Option<Vec<u8>>,
+    repair: bool,
+    wait: bool,
+    output: Output,
+    remote: Option<Address>,
+}
 
 pub enum SubCommand {
     Get {
@@ -10,16 +35,57 @@ pub enum SubCommand {
     },
     Set {
         key: String,
-        value: String,
+        value: Vec<u8>,
         force: bool,
+        ttl: Option<Duration>,
+        expected: Option<Precondition>,
     },
     Remove {
         key: String,
     },
+    List {
+        prefix: Option<String>,
+        glob: Option<String>,
+        keys_only: bool,
+    },
+    Scan {
+        from: Option<String>,
+        to: Option<String>,
+    },
+    History {
+        key: String,
+    },
+    Rollback {
+        key: String,
+        to: Option<u64>,
+    },
+    Purge,
+    Import {
+        format: transfer::Format,
+        replace: bool,
+    },
+    Export {
+        format: transfer::Format,
+    },
+    Batch,
+    NsList,
+    NsCreate {
+        name: String,
+    },
+    NsDrop {
+        name: String,
+    },
     Init,
+    Serve {
+        address: Address,
+        protocol: Protocol,
+    },
+    Watch {
+        target: watch::Target,
+    },
 }
 
-pub fn get_args() -> std::io::Result<SubCommand> {
+pub fn get_args() -> std::io::Result<Config> {
     let arg_key = Arg::new("key")
         .index(1)
         .takes_value(true)
@@ -29,30 +95,175 @@ pub fn get_args() -> std::io::Result<SubCommand> {
     let arg_value = Arg::new("value")
         .index(2)
         .takes_value(true)
-        .required(true)
+        .required_unless_present("from_file")
         .help("The value.");
 
+    let arg_name = Arg::new("name")
+        .index(1)
+        .takes_value(true)
+        .required(true)
+        .help("The name of the namespace.");
+
+    let arg_format = Arg::new("format")
+        .long("format")
+        .short('F')
+        .takes_value(true)
+        .value_name("FORMAT")
+        .possible_values(transfer::Format::NAMES)
+        .default_value("tsv")
+        .help("The file format.");
+
     let matches = Command::new(clap::crate_name!())
         .version(clap::crate_version!())
         .author(clap::crate_authors!())
         .about(clap::crate_description!())
         .subcommand_required(true)
+        .arg(
+            Arg::new("db")
+                .long("db")
+                .env("KVSTORE_DB")
+                .global(true)
+                .takes_value(true)
+                .value_name("PATH")
+                .help("The database file. [default: $XDG_DATA_HOME/kvstore/kv.db]"),
+        )
+        .arg(
+            Arg::new("backend")
+                .long("backend")
+                .env("KVSTORE_BACKEND")
+                .global(true)
+                .takes_value(true)
+                .value_name("BACKEND")
+                .possible_values(BackendKind::NAMES)
+                .default_value("tsv")
+                .help("How the database is stored: a tsv file with a write-ahead log, a json file, an sqlite file, or only in memory."),
+        )
+        .arg(
+            Arg::new("ns")
+                .long("ns")
+                .env("KVSTORE_NS")
+                .global(true)
+                .takes_value(true)
+                .value_name("NAME")
+                .default_value(DEFAULT_NAMESPACE)
+                .help("The namespace to use keys from."),
+        )
+        .arg(
+            Arg::new("key_file")
+                .long("key-file")
+                .global(true)
+                .takes_value(true)
+                .value_name("PATH")
+                .help("Encrypts the database with the contents of the file as the passphrase, less one trailing newline. [default: $KVSTORE_PASSPHRASE]"),
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .global(true)
+                .takes_value(true)
+                .value_name("FORMAT")
+                .possible_values(Output::NAMES)
+                .default_value("text")
+                .help("How results are printed."),
+        )
+        .arg(
+            Arg::new("remote")
+                .long("remote")
+                .env("KVSTORE_REMOTE")
+                .global(true)
+                .takes_value(true)
+                .value_name("ADDRESS")
+                .validator(|a| a.parse::<Address>())
+                .help("Sends get, set, remove, list and watch to a server started with serve, at unix:PATH or tcp:HOST:PORT."),
+        )
+        .arg(
+            Arg::new("repair")
+                .long("repair")
+                .global(true)
+                .takes_value(false)
+                .help("Quarantines corrupt lines in the database files instead of failing."),
+        )
+        .arg(
+            Arg::new("wait")
+                .long("wait")
+                .global(true)
+                .takes_value(false)
+                .overrides_with("no_wait")
+                .help("Waits for other processes using the database to finish. (default)"),
+        )
+        .arg(
+            Arg::new("no_wait")
+                .long("no-wait")
+                .global(true)
+                .takes_value(false)
+                .overrides_with("wait")
+                .help("Fails instead of waiting if another process is using the database."),
+        )
         .subcommand(
             Command::new("get")
                 .about("Gets the value in the database associated with a given key.")
-                .arg(&arg_key),
+                .arg(&arg_key)
+                .arg(
+                    Arg::new("raw")
+                        .long("raw")
+                        .takes_value(false)
+                        .help("Writes the value exactly as stored, like --output raw."),
+                ),
         )
         .subcommand(
             Command::new("set")
                 .about("Sets the key/value pair in the database.")
                 .arg(&arg_key)
                 .arg(&arg_value)
+                .arg(
+                    Arg::new("from_file")
+                        .long("from-file")
+                        .takes_value(true)
+                        .value_name("PATH")
+                        .conflicts_with("value")
+                        .help("Reads the value from a file, or from stdin if PATH is '-'."),
+                )
                 .arg(
                     Arg::new("force")
                         .short('f')
                         .long("force")
                         .takes_value(false)
                         .help("Overwrites existing key/value in the database."),
+                )
+                .arg(
+                    Arg::new("ttl")
+                        .long("ttl")
+                        .takes_value(true)
+                        .value_name("DURATION")
+                        .validator(parse_ttl)
+                        .help("Expires the key/value pair after a time, e.g. 30s, 10m, 2h or 7d."),
+                )
+                .arg(
+                    Arg::new("if_value")
+                        .long("if-value")
+                        .takes_value(true)
+                        .value_name("VALUE")
+                        .help("Only sets the key if it is currently set to VALUE."),
+                )
+                .arg(
+                    Arg::new("if_absent")
+                        .long("if-absent")
+                        .takes_value(false)
+                        .help("Only sets the key if it doesn't exist yet."),
+                )
+                .arg(
+                    Arg::new("if_version")
+                        .long("if-version")
+                        .takes_value(true)
+                        .value_name("VERSION")
+                        .validator(|v| v.parse::<u64>())
+                        .help("Only sets the key if it is currently at VERSION. (see get -o json)"),
+                )
+                .group(
+                    ArgGroup::new("precondition")
+                        .args(&["if_value", "if_absent", "if_version"])
+                        .conflicts_with("force"),
                 ),
         )
         .subcommand(
@@ -60,60 +271,557 @@ pub fn get_args() -> std::io::Result<SubCommand> {
                 .about("Removes the key/value pair in the database for a given key.")
                 .arg(&arg_key),
         )
+        .subcommand(
+            Command::new("list")
+                .about("Lists the key/value pairs in the database, sorted by key.")
+                .arg(
+                    Arg::new("prefix")
+                        .long("prefix")
+                        .takes_value(true)
+                        .value_name("PREFIX")
+                        .help("Only lists keys that start with the prefix."),
+                )
+                .arg(
+                    Arg::new("glob")
+                        .long("glob")
+                        .takes_value(true)
+                        .value_name("PATTERN")
+                        .help("Only lists keys that match the pattern. ('*' matches any characters, '?' matches one)"),
+                )
+                .arg(
+                    Arg::new("keys_only")
+                        .long("keys-only")
+                        .takes_value(false)
+                        .help("Lists the keys without their values."),
+                ),
+        )
+        .subcommand(
+            Command::new("scan")
+                .about("Lists the key/value pairs in a range of keys, sorted by key.")
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .takes_value(true)
+                        .value_name("KEY")
+                        .help("Starts at this key. (inclusive)"),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .takes_value(true)
+                        .value_name("KEY")
+                        .help("Stops before this key. (exclusive)"),
+                ),
+        )
+        .subcommand(
+            Command::new("history")
+                .about("Shows the current value of a key and its earlier values, newest first.")
+                .arg(&arg_key),
+        )
+        .subcommand(
+            Command::new("watch")
+                .about("Prints changes to a key, or to the keys with a prefix, as they happen.")
+                .arg(
+                    Arg::new("key")
+                        .index(1)
+                        .takes_value(true)
+                        .required_unless_present("prefix")
+                        .conflicts_with("prefix")
+                        .help("The key."),
+                )
+                .arg(
+                    Arg::new("prefix")
+                        .long("prefix")
+                        .takes_value(true)
+                        .value_name("PREFIX")
+                        .help("Watch the keys that start with PREFIX instead."),
+                ),
+        )
+        .subcommand(
+            Command::new("rollback")
+                .about("Sets a key back to an earlier value.")
+                .arg(&arg_key)
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .takes_value(true)
+                        .value_name("VERSION")
+                        .validator(|v| v.parse::<u64>())
+                        .help("The version to go back to. [default: the previous version]"),
+                ),
+        )
+        .subcommand(
+            Command::new("purge")
+                .about("Removes the key/value pairs in the namespace that have expired."),
+        )
+        .subcommand(
+            Command::new("import")
+                .about("Imports key/value pairs read from stdin.")
+                .arg(&arg_format)
+                .arg(
+                    Arg::new("merge")
+                        .long("merge")
+                        .visible_alias("no-overwrite")
+                        .takes_value(false)
+                        .overrides_with("replace")
+                        .help(
+                            "Fails without importing anything if a key already exists. (default)",
+                        ),
+                )
+                .arg(
+                    Arg::new("replace")
+                        .long("replace")
+                        .visible_alias("overwrite")
+                        .takes_value(false)
+                        .overrides_with("merge")
+                        .help("Overwrites existing keys with the imported values, keeping the rest."),
+                ),
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Exports all key/value pairs to stdout, sorted by key.")
+                .arg(&arg_format),
+        )
+        .subcommand(Command::new("batch").about(
+            "Applies set, remove and check operations read from stdin, all or nothing.",
+        ))
+        .subcommand(
+            Command::new("ns")
+                .about("Manages the namespaces that keep separate sets of keys.")
+                .subcommand_required(true)
+                .subcommand(Command::new("list").about("Lists the namespaces in the database."))
+                .subcommand(
+                    Command::new("create")
+                        .about("Creates a new, empty namespace.")
+                        .arg(&arg_name),
+                )
+                .subcommand(
+                    Command::new("drop")
+                        .about("Removes a namespace along with all of its keys.")
+                        .arg(&arg_name),
+                ),
+        )
         .subcommand(Command::new("init").about("Initalize a new empty key/value database."))
+        .subcommand(
+            Command::new("serve")
+                .about("Keeps the database open and answers requests from clients using --remote.")
+                .arg(
+                    Arg::new("listen")
+                        .long("listen")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("ADDRESS")
+                        .validator(|a| a.parse::<Address>())
+                        .help("Where to listen, as unix:PATH or tcp:HOST:PORT."),
+                )
+                .arg(
+                    Arg::new("protocol")
+                        .long("protocol")
+                        .takes_value(true)
+                        .value_name("PROTOCOL")
+                        .possible_values(Protocol::NAMES)
+                        .default_value("line")
+                        .help("The protocol to speak: line for --remote, resp for Redis clients, or http for a REST API."),
+                ),
+        )
         .get_matches();
 
-    match matches.subcommand() {
-        Some(("get", get_matches)) => Ok(SubCommand::Get {
+    let cmd = match matches.subcommand() {
+        Some(("get", get_matches)) => SubCommand::Get {
             key: get_matches.value_of("key").unwrap().to_string(),
-        }),
-        Some(("set", set_matches)) => Ok(SubCommand::Set {
+        },
+        Some(("set", set_matches)) => SubCommand::Set {
             key: set_matches.value_of("key").unwrap().to_string(),
-            value: set_matches.value_of("value").unwrap().to_string(),
+            value: match set_matches.value_of("from_file") {
+                Some("-") => {
+                    let mut value = Vec::new();
+                    std::io::stdin().read_to_end(&mut value)?;
+                    value
+                }
+                Some(path) => std::fs::read(path)?,
+                None => set_matches.value_of("value").unwrap().as_bytes().to_vec(),
+            },
             force: set_matches.is_present("force"),
-        }),
-        Some(("remove", rm_matches)) => Ok(SubCommand::Remove {
+            ttl: set_matches
+                .value_of("ttl")
+                .map(|ttl| parse_ttl(ttl).unwrap()),
+            expected: if let Some(value) = set_matches.value_of("if_value") {
+                Some(Precondition::Value(value.as_bytes().to_vec()))
+            } else if set_matches.is_present("if_absent") {
+                Some(Precondition::Absent)
+            } else if set_matches.is_present("if_version") {
+                Some(Precondition::Version(
+                    set_matches.value_of_t("if_version").map_err(Error::other)?,
+                ))
+            } else {
+                None
+            },
+        },
+        Some(("remove", rm_matches)) => SubCommand::Remove {
             key: rm_matches.value_of("key").unwrap().to_string(),
-        }),
-        Some(("init", _init_matches)) => Ok(SubCommand::Init {}),
+        },
+        Some(("list", list_matches)) => SubCommand::List {
+            prefix: list_matches.value_of("prefix").map(String::from),
+            glob: list_matches.value_of("glob").map(String::from),
+            keys_only: list_matches.is_present("keys_only"),
+        },
+        Some(("scan", scan_matches)) => {
+            let from = scan_matches.value_of("from").map(String::from);
+            let to = scan_matches.value_of("to").map(String::from);
+            if let (Some(from), Some(to)) = (&from, &to)
+                && from > to
+            {
+                return Err(Error::other("--from must not come after --to."));
+            }
+            SubCommand::Scan { from, to }
+        }
+        Some(("history", history_matches)) => SubCommand::History {
+            key: history_matches.value_of("key").unwrap().to_string(),
+        },
+        Some(("watch", watch_matches)) => SubCommand::Watch {
+            target: match watch_matches.value_of("prefix") {
+                Some(prefix) => watch::Target::Prefix(prefix.to_string()),
+                None => watch::Target::Key(watch_matches.value_of("key").unwrap().to_string()),
+            },
+        },
+        Some(("rollback", rollback_matches)) => SubCommand::Rollback {
+            key: rollback_matches.value_of("key").unwrap().to_string(),
+            to: match rollback_matches.is_present("to") {
+                true => Some(rollback_matches.value_of_t("to").map_err(Error::other)?),
+                false => None,
+            },
+        },
+        Some(("purge", _purge_matches)) => SubCommand::Purge {},
+        Some(("import", import_matches)) => SubCommand::Import {
+            format: import_matches.value_of_t("format").map_err(Error::other)?,
+            replace: import_matches.is_present("replace"),
+        },
+        Some(("export", export_matches)) => SubCommand::Export {
+            format: export_matches.value_of_t("format").map_err(Error::other)?,
+        },
+        Some(("batch", _batch_matches)) => SubCommand::Batch {},
+        Some(("ns", ns_matches)) => match ns_matches.subcommand() {
+            Some(("create", create_matches)) => SubCommand::NsCreate {
+                name: create_matches.value_of("name").unwrap().to_string(),
+            },
+            Some(("drop", drop_matches)) => SubCommand::NsDrop {
+                name: drop_matches.value_of("name").unwrap().to_string(),
+            },
+            _ => SubCommand::NsList {},
+        },
+        Some(("init", _init_matches)) => SubCommand::Init {},
+        Some(("serve", serve_matches)) => SubCommand::Serve {
+            address: serve_matches.value_of_t("listen").map_err(Error::other)?,
+            protocol: serve_matches.value_of_t("protocol").map_err(Error::other)?,
+        },
 
         // This should never get executed since get_matches() will bubble up an
         // error if there is not a subcommand provided.
-        _ => Err(Error::new(
-            ErrorKind::Other,
-            "Subcommand not specified or was unknown.",
-        )),
-    }
+        _ => return Err(Error::other("Subcommand not specified or was unknown.")),
+    };
+
+    let db_path = match matches.value_of("db") {
+        Some(path) => path.to_string(),
+        None => default_db_path()?,
+    };
+
+    // The passphrase is never taken as an argument, where other users could
+    // see it. A key file usually ends in a newline that isn't part of the
+    // passphrase, so the same secret works either way.
+    let passphrase = match matches.value_of("key_file") {
+        Some(path) => {
+            let mut passphrase = std::fs::read(path)?;
+            if passphrase.ends_with(b"\n") {
+                passphrase.pop();
+                if passphrase.ends_with(b"\r") {
+                    passphrase.pop();
+                }
+            }
+            Some(passphrase)
+        }
+        None => std::env::var_os("KVSTORE_PASSPHRASE")
+            .filter(|passphrase| !passphrase.is_empty())
+            .map(|passphrase| passphrase.into_encoded_bytes()),
+    };
+
+    Ok(Config {
+        cmd,
+        db_path,
+        backend: matches.value_of_t("backend").map_err(Error::other)?,
+        namespace: matches.value_of("ns").unwrap().to_string(),
+        passphrase,
+        repair: matches.is_present("repair"),
+        wait: !matches.is_present("no_wait"),
+        output: match matches.subcommand_matches("get") {
+            Some(get_matches) if get_matches.is_present("raw") => Output::Raw,
+            _ => matches.value_of_t("output").map_err(Error::other)?,
+        },
+        remote: match matches.is_present("remote") {
+            true => Some(matches.value_of_t("remote").map_err(Error::other)?),
+            false => None,
+        },
+    })
+}
+
+// Location of the database when no path is given, following the XDG base
+// directory spec. The directory is created if it doesn't exist yet.
+fn default_db_path() -> std::io::Result<String> {
+    let data_home = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) if PathBuf::from(&dir).is_absolute() => PathBuf::from(dir),
+        _ => match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".local").join("share"),
+            None => {
+                return Err(Error::other(
+                    "Can't determine the database location; use --db or set KVSTORE_DB.",
+                ));
+            }
+        },
+    };
+
+    let dir = data_home.join(clap::crate_name!());
+    std::fs::create_dir_all(&dir)?;
+
+    Ok(dir.join("kv.db").to_string_lossy().into_owned())
 }
 
-pub fn run(cmd: SubCommand) -> std::io::Result<()> {
-    let mut db = Database::from_disk("kv.db")?;
+pub fn run(config: Config) -> Result<(), DbError> {
+    // A server has the database open on the client's behalf.
+    if let Some(address) = config.remote.clone() {
+        return client::run(config, &address);
+    }
+
+    // Watching reads the database again each time its files change, rather
+    // than keeping it open.
+    if let SubCommand::Watch { target } = &config.cmd {
+        return watch::watch_files(
+            &config.db_path,
+            config.backend,
+            &config.namespace,
+            config.passphrase.as_deref(),
+            target,
+            |event| {
+                output::event(config.output, event);
+                Ok(())
+            },
+        );
+    }
+
+    // Reads can share the database with other processes and never write to
+    // it; anything that modifies it needs the database to itself.
+    let read_only = matches!(
+        config.cmd,
+        SubCommand::Get { .. }
+            | SubCommand::List { .. }
+            | SubCommand::Scan { .. }
+            | SubCommand::History { .. }
+            | SubCommand::NsList
+            | SubCommand::Export { .. }
+    );
 
-    match cmd {
+    let mut db = if config.repair {
+        if config.backend != BackendKind::Tsv {
+            return Err(DbError::Io(Error::new(
+                ErrorKind::InvalidInput,
+                "--repair only works with the tsv backend.",
+            )));
+        }
+        let (db, quarantined) = Database::repair(
+            &config.db_path,
+            config.backend,
+            config.wait,
+            config.passphrase.as_deref(),
+        )?;
+        if quarantined > 0 {
+            eprintln!(
+                "Quarantined {} corrupt line(s) to {}.",
+                quarantined,
+                database::quarantine_filename(&config.db_path)
+            );
+        }
+        db
+    } else if read_only {
+        Database::open_read_only(
+            &config.db_path,
+            config.backend,
+            config.wait,
+            config.passphrase.as_deref(),
+        )?
+    } else {
+        Database::open(
+            &config.db_path,
+            config.backend,
+            Lock::Exclusive,
+            config.wait,
+            config.passphrase.as_deref(),
+        )?
+    };
+
+    // Namespaces are managed as a whole, init starts over without any, and
+    // the clients of a server each pick their own.
+    match config.cmd {
+        SubCommand::NsList
+        | SubCommand::NsCreate { .. }
+        | SubCommand::NsDrop { .. }
+        | SubCommand::Init
+        | SubCommand::Serve { .. } => {}
+        _ => db.use_namespace(&config.namespace)?,
+    }
+
+    match config.cmd {
         SubCommand::Get { key } => match db.get(&key) {
             Some((k, v)) => {
-                println!("{} : {}", k, v);
+                output::found(config.output, k, v, db.version(&key).unwrap_or(1));
                 Ok(())
             }
-            None => Err(Error::new(
-                ErrorKind::NotFound,
-                format!("No entry found for key '{}'.", key),
-            )),
+            None => Err(DbError::NotFound(key)),
         },
-        SubCommand::Set { key, value, force } => {
-            db.insert(key, value, force)?;
+        SubCommand::Set {
+            key,
+            value,
+            force,
+            ttl,
+            expected,
+        } => {
+            match (expected, ttl) {
+                (Some(expected), ttl) => {
+                    db.compare_and_swap(key, value, &expected, ttl)?;
+                }
+                (None, Some(ttl)) => {
+                    db.insert_with_ttl(key, value, force, ttl)?;
+                }
+                (None, None) => {
+                    db.insert(key, value, force)?;
+                }
+            }
             Ok(())
         }
-        SubCommand::Remove { key } => match db.remove(&key) {
+        SubCommand::Remove { key } => match db.remove(&key)? {
             Some((k, v)) => {
-                println!("({} : {}) removed from database.", k, v);
+                output::removed(config.output, &k, &v);
+                Ok(())
+            }
+            None => Err(DbError::NotFound(key)),
+        },
+        SubCommand::List {
+            prefix,
+            glob,
+            keys_only,
+        } => {
+            let entries = db
+                .iter()
+                .filter(|(k, _)| prefix.as_ref().is_none_or(|p| k.starts_with(p.as_str())))
+                .filter(|(k, _)| glob.as_ref().is_none_or(|g| glob_match(g, k)));
+
+            output::entries(config.output, entries, keys_only);
+            Ok(())
+        }
+        SubCommand::Scan { from, to } => {
+            let start = from.as_deref().map_or(Bound::Unbounded, Bound::Included);
+            let end = to.as_deref().map_or(Bound::Unbounded, Bound::Excluded);
+
+            output::entries(config.output, db.range((start, end)), false);
+            Ok(())
+        }
+        SubCommand::History { key } => match (db.get(&key), db.version(&key), db.history(&key)) {
+            (Some((_, value)), Some(version), Some(revisions)) => {
+                output::history(config.output, (value, version), revisions);
                 Ok(())
             }
-            None => Err(Error::new(
-                ErrorKind::NotFound,
-                format!("No entry found for key '{}'.", key),
-            )),
+            _ => Err(DbError::NotFound(key)),
         },
+        SubCommand::Rollback { key, to } => {
+            db.rollback(&key, to)?;
+            Ok(())
+        }
+        SubCommand::Purge => {
+            let purged = db.purge()?;
+            output::purged(config.output, &purged);
+            Ok(())
+        }
+        SubCommand::Import { format, replace } => {
+            let mut contents = String::new();
+            std::io::stdin().read_to_string(&mut contents)?;
+            let pairs = transfer::parse(format, &contents)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            db.insert_all(pairs, replace)?;
+            Ok(())
+        }
+        SubCommand::Export { format } => {
+            transfer::write(format, &mut std::io::stdout().lock(), db.iter())?;
+            Ok(())
+        }
+        SubCommand::Batch => {
+            let mut script = String::new();
+            std::io::stdin().read_to_string(&mut script)?;
+            let ops = batch::parse(&script).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+            db.transaction(|tx| batch::apply(tx, ops))
+        }
+        SubCommand::NsList => {
+            output::namespaces(config.output, db.namespaces());
+            Ok(())
+        }
+        SubCommand::NsCreate { name } => db.create_namespace(&name),
+        SubCommand::NsDrop { name } => {
+            db.drop_namespace(&name)?;
+            Ok(())
+        }
         SubCommand::Init => db.init(),
+        SubCommand::Serve { address, protocol } => {
+            server::serve(db, &address, protocol)?;
+            Ok(())
+        }
+        SubCommand::Watch { .. } => unreachable!(),
     }
 }
+
+// Parse a time to live such as "90", "30s", "10m", "2h" or "7d".
+// A number without a unit is a number of seconds.
+fn parse_ttl(ttl: &str) -> Result<Duration, String> {
+    let (number, unit) = match ttl.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => ttl.split_at(i),
+        None => (ttl, "s"),
+    };
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("Unknown time unit '{}'.", unit)),
+    };
+
+    match number.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(Duration::from_secs(n.saturating_mul(seconds))),
+        _ => Err(format!("Invalid time to live '{}'.", ttl)),
+    }
+}
+
+// Match a key against a shell-style pattern where '*' matches any run of
+// characters and '?' matches exactly one.
+fn glob_match(pattern: &str, key: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let key: Vec<char> = key.chars().collect();
+    let (mut p, mut k) = (0, 0);
+
+    // Where to resume if the characters after the most recent '*' stop
+    // matching: the pattern just past the '*', and the key one character
+    // further along than last time.
+    let mut backtrack = None;
+
+    while k < key.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == key[k]) {
+            p += 1;
+            k += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            p += 1;
+            backtrack = Some((p, k));
+        } else if let Some((star_p, star_k)) = backtrack {
+            p = star_p;
+            k = star_k + 1;
+            backtrack = Some((star_p, k));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
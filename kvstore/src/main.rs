@@ -1,6 +1,10 @@
 fn main() {
-    if let Err(e) = kvstore::get_args().and_then(kvstore::run) {
+    let result = kvstore::get_args()
+        .map_err(kvstore::DbError::from)
+        .and_then(kvstore::run);
+
+    if let Err(e) = result {
         eprintln!("{e}");
-        std::process::exit(1);
+        std::process::exit(e.exit_code());
     }
 }
@@ -0,0 +1,236 @@
+use crate::database::{Revision, format};
+use crate::watch::Event;
+use serde_json::json;
+use std::borrow::Cow;
+use std::io::Write;
+use std::str::FromStr;
+
+// How results are written to stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    // "key : value" lines meant for people.
+    Text,
+    // A JSON object per entry, or an array of them for lists.
+    // Like text, shows binary values with invalid UTF-8 replaced.
+    Json,
+    // Values exactly as stored, with nothing added around a single value.
+    Raw,
+    // Tab separated key/value lines, escaped like the database file.
+    Tsv,
+}
+
+impl Output {
+    pub const NAMES: [&'static str; 4] = ["text", "json", "raw", "tsv"];
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Output::Text),
+            "json" => Ok(Output::Json),
+            "raw" => Ok(Output::Raw),
+            "tsv" => Ok(Output::Tsv),
+            _ => Err(format!("Unknown output format '{}'.", s)),
+        }
+    }
+}
+
+// Print a single key/value pair.
+pub fn entry(output: Output, key: &str, value: &[u8]) {
+    match output {
+        Output::Text => println!("{} : {}", key, text(value)),
+        Output::Json => println!("{}", json!({ "key": key, "value": text(value) })),
+        Output::Raw => raw(value, ""),
+        Output::Tsv => println!("{}\t{}", format::escape(key), format::escape_bytes(value)),
+    }
+}
+
+// A value as text, with any invalid UTF-8 replaced.
+fn text(value: &[u8]) -> Cow<'_, str> {
+    String::from_utf8_lossy(value)
+}
+
+// Write a value to stdout exactly as it is, followed by 'end'.
+fn raw(value: &[u8], end: &str) {
+    let mut stdout = std::io::stdout().lock();
+    stdout
+        .write_all(value)
+        .and_then(|()| stdout.write_all(end.as_bytes()))
+        .expect("failed printing to stdout");
+}
+
+// Print the key/value pair found for a key. JSON output also includes the
+// version of the value, for use with `set --if-version`.
+pub fn found(output: Output, key: &str, value: &[u8], version: u64) {
+    match output {
+        Output::Json => println!(
+            "{}",
+            json!({ "key": key, "value": text(value), "version": version })
+        ),
+        _ => entry(output, key, value),
+    }
+}
+
+// Print a key/value pair that was removed from the database.
+pub fn removed(output: Output, key: &str, value: &[u8]) {
+    match output {
+        Output::Text => println!("({} : {}) removed from database.", key, text(value)),
+        _ => entry(output, key, value),
+    }
+}
+
+// Print a change to a watched key, along with its value before the change.
+pub fn event(output: Output, event: &Event) {
+    let (name, key, old, new) = match event {
+        Event::Set { key, old, new } => ("set", key, old.as_deref(), Some(new.as_slice())),
+        Event::Remove { key, old } => ("remove", key, Some(old.as_slice()), None),
+        Event::Expire { key, old } => ("expire", key, Some(old.as_slice()), None),
+    };
+    match output {
+        Output::Text => {
+            let mut line = format!("{} {}", name, key);
+            if let Some(new) = new {
+                line.push_str(&format!(" : {}", text(new)));
+            }
+            if let Some(old) = old {
+                line.push_str(&format!(" (was {})", text(old)));
+            }
+            println!("{}", line);
+        }
+        Output::Json => println!(
+            "{}",
+            json!({ "event": name, "key": key, "old": old.map(text), "new": new.map(text) })
+        ),
+        // One value per line, with an empty line for a key that's gone.
+        Output::Raw => raw(new.unwrap_or_default(), "\n"),
+        Output::Tsv => println!(
+            "{}\t{}\t{}\t{}",
+            name,
+            format::escape(key),
+            old.map(format::escape_bytes).unwrap_or_default(),
+            new.map(format::escape_bytes).unwrap_or_default()
+        ),
+    }
+}
+
+// Print a list of key/value pairs, or just their keys.
+pub fn entries<'a>(
+    output: Output,
+    entries: impl Iterator<Item = (&'a String, &'a [u8])>,
+    keys_only: bool,
+) {
+    if output == Output::Json {
+        let list: Vec<_> = entries
+            .map(|(k, v)| match keys_only {
+                true => json!(k),
+                false => json!({ "key": k, "value": text(v) }),
+            })
+            .collect();
+        println!("{}", json!(list));
+        return;
+    }
+
+    for (k, v) in entries {
+        match (output, keys_only) {
+            (Output::Tsv, true) => println!("{}", format::escape(k)),
+            (_, true) => println!("{}", k),
+            // One value per line, since raw values can't be told apart otherwise.
+            (Output::Raw, false) => raw(v, "\n"),
+            (_, false) => entry(output, k, v),
+        }
+    }
+}
+
+// Print the keys of expired key/value pairs that were purged.
+pub fn purged(output: Output, keys: &[String]) {
+    match output {
+        Output::Text => println!("Purged {} expired key(s).", keys.len()),
+        Output::Json => println!("{}", json!(keys)),
+        Output::Raw => keys.iter().for_each(|k| println!("{}", k)),
+        Output::Tsv => keys.iter().for_each(|k| println!("{}", format::escape(k))),
+    }
+}
+
+// Print the names of namespaces.
+pub fn namespaces<'a>(output: Output, names: impl Iterator<Item = &'a String>) {
+    match output {
+        Output::Json => println!("{}", json!(names.collect::<Vec<_>>())),
+        Output::Tsv => names.for_each(|name| println!("{}", format::escape(name))),
+        _ => names.for_each(|name| println!("{}", name)),
+    }
+}
+
+// Print the current value of a key followed by its earlier values, newest
+// first.
+pub fn history(output: Output, current: (&[u8], u64), revisions: &[Revision]) {
+    let (value, version) = current;
+    if output == Output::Json {
+        let list: Vec<_> = std::iter::once(
+            json!({ "version": version, "value": text(value), "replaced_at": null }),
+        )
+        .chain(revisions.iter().rev().map(|r| {
+            json!({ "version": r.version, "value": text(&r.value), "replaced_at": r.replaced_at })
+        }))
+        .collect();
+        println!("{}", json!(list));
+        return;
+    }
+
+    match output {
+        Output::Text => println!("{} : {} (current)", version, text(value)),
+        Output::Raw => raw(value, "\n"),
+        _ => println!("{}\t{}\t", version, format::escape_bytes(value)),
+    }
+    for r in revisions.iter().rev() {
+        match output {
+            Output::Text => println!(
+                "{} : {} (replaced {})",
+                r.version,
+                text(&r.value),
+                utc_time(r.replaced_at)
+            ),
+            Output::Raw => raw(&r.value, "\n"),
+            _ => println!(
+                "{}\t{}\t{}",
+                r.version,
+                format::escape_bytes(&r.value),
+                r.replaced_at
+            ),
+        }
+    }
+}
+
+// Format a Unix time in milliseconds as a UTC date and time.
+fn utc_time(millis: u64) -> String {
+    let seconds = millis / 1000;
+    let (days, time) = (seconds / 86400, seconds % 86400);
+
+    // Convert days since 1970-01-01 to a date in the proleptic Gregorian
+    // calendar, counting years from March so leap days come last.
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
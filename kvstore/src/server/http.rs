@@ -0,0 +1,344 @@
+// A front end serving a REST API over HTTP/1.1, with JSON responses:
+//
+//     GET /keys?prefix=PREFIX   Lists key/value pairs, optionally by prefix.
+//     GET /keys/KEY             Gets a key/value pair and its version.
+//     PUT /keys/KEY             Sets a key to the request body.
+//     DELETE /keys/KEY          Removes a key.
+//
+// A PUT fails if the key already exists, unless given `?force=true`, and
+// takes `?ttl=DURATION` to make the key expire, like `set`. The ETag of a key
+// is its version, and PUT and DELETE are made conditional with `If-Match`
+// (the key is at the version given, or exists at all for `*`) or
+// `If-None-Match: *` (the key doesn't exist), failing with 412 otherwise.
+// Values are shown in JSON like `-o json`, with invalid UTF-8 replaced.
+// Every connection uses the default namespace and handles a single request.
+
+use super::Shared;
+use crate::database::{DbError, Precondition};
+use crate::parse_ttl;
+use serde_json::{Value, json};
+use std::io::{BufRead, ErrorKind, Read, Write};
+
+// The largest request headers accepted, so a client can't use up memory.
+const MAX_HEADER_LEN: u64 = 64 * 1024;
+
+// A request, with the path split from its query and percent-decoded.
+struct Request {
+    method: String,
+    path: Vec<u8>,
+    query: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// A response, along with the version of the key it's about for the ETag.
+struct Response {
+    status: u16,
+    version: Option<u64>,
+    body: Value,
+}
+
+impl Response {
+    fn ok(body: Value) -> Response {
+        Response {
+            status: 200,
+            version: None,
+            body,
+        }
+    }
+
+    fn error(status: u16, message: impl Into<String>) -> Response {
+        Response {
+            status,
+            version: None,
+            body: json!({ "error": message.into() }),
+        }
+    }
+}
+
+impl From<DbError> for Response {
+    fn from(e: DbError) -> Self {
+        let status = match &e {
+            DbError::NotFound(_) | DbError::NamespaceNotFound(_) => 404,
+            DbError::AlreadyExists(_) => 409,
+            DbError::PreconditionFailed(_) => 412,
+            _ => 500,
+        };
+        Response::error(status, e.to_string())
+    }
+}
+
+// Answer the request read from a connection, then close it.
+pub fn handle(
+    mut reader: impl BufRead,
+    mut writer: impl Write,
+    db: &Shared,
+) -> std::io::Result<()> {
+    let response = match read_request(&mut reader)? {
+        None => return Ok(()),
+        Some(Ok(request)) => respond(request, db).unwrap_or_else(|response| response),
+        Some(Err(response)) => response,
+    };
+
+    let body = response.body.to_string() + "\n";
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        reason(response.status),
+        body.len()
+    );
+    if let Some(version) = response.version {
+        head.push_str(&format!("ETag: \"{}\"\r\n", version));
+    }
+    head.push_str("\r\n");
+    writer.write_all(head.as_bytes())?;
+    writer.write_all(body.as_bytes())?;
+    writer.flush()
+}
+
+// Read a request. Returns None if the connection is closed before sending
+// anything, or a response to send back if the request is malformed.
+fn read_request(reader: &mut impl BufRead) -> std::io::Result<Option<Result<Request, Response>>> {
+    let bad_request = |message: &str| Ok(Some(Err(Response::error(400, message))));
+    let mut head = reader.by_ref().take(MAX_HEADER_LEN);
+
+    let mut line = String::new();
+    if read_line(&mut head, &mut line)? == 0 {
+        return Ok(None);
+    }
+    let [method, target, version] = line.split(' ').collect::<Vec<_>>()[..] else {
+        return bad_request("Invalid request line.");
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Ok(Some(Err(Response::error(
+            505,
+            "Only HTTP/1.x is supported.",
+        ))));
+    }
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let Some(path) = percent_decode(path, false) else {
+        return bad_request("Invalid percent-encoding in path.");
+    };
+    let Some(query) = parse_query(query) else {
+        return bad_request("Invalid query string.");
+    };
+    let method = method.to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if read_line(&mut head, &mut line)? == 0 {
+            return bad_request("Incomplete request headers.");
+        }
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return bad_request("Invalid header.");
+        };
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    let mut request = Request {
+        method,
+        path,
+        query,
+        headers,
+        body: Vec::new(),
+    };
+    if request.header("Transfer-Encoding").is_some() {
+        return Ok(Some(Err(Response::error(
+            411,
+            "Send the body with a Content-Length.",
+        ))));
+    }
+    if let Some(len) = request.header("Content-Length") {
+        let Ok(len) = len.parse::<u64>() else {
+            return bad_request("Invalid Content-Length.");
+        };
+        reader.take(len).read_to_end(&mut request.body)?;
+        if request.body.len() as u64 != len {
+            return bad_request("Incomplete request body.");
+        }
+    }
+
+    Ok(Some(Ok(request)))
+}
+
+// Read a line without its line ending. Headers that go past the size limit
+// or aren't valid UTF-8 count as the end of the request.
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> std::io::Result<usize> {
+    match reader.read_line(line) {
+        Ok(0) => Ok(0),
+        Ok(_) if !line.ends_with('\n') => Ok(0),
+        Ok(n) => {
+            let len = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(len);
+            Ok(n)
+        }
+        Err(e) if e.kind() == ErrorKind::InvalidData => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+// Carry out a request against the database.
+fn respond(request: Request, db: &Shared) -> Result<Response, Response> {
+    let Some(path) = request.path.strip_prefix(b"/keys") else {
+        return Err(Response::error(404, "Not found."));
+    };
+    let key = match path {
+        b"" | b"/" => None,
+        _ => match path
+            .strip_prefix(b"/")
+            .map(|key| String::from_utf8(key.to_vec()))
+        {
+            Some(Ok(key)) => Some(key),
+            Some(Err(_)) => return Err(Response::error(400, "Keys must be valid UTF-8.")),
+            None => return Err(Response::error(404, "Not found.")),
+        },
+    };
+
+    let mut db = db.lock();
+    match (request.method.as_str(), key) {
+        ("GET", None) => {
+            let prefix = request.query("prefix").unwrap_or("");
+            let list: Vec<Value> = db
+                .iter()
+                .filter(|(k, _)| k.starts_with(prefix))
+                .map(|(k, v)| json!({ "key": k, "value": String::from_utf8_lossy(v) }))
+                .collect();
+            Ok(Response::ok(json!(list)))
+        }
+        ("GET", Some(key)) => match db.get(&key) {
+            Some((_, value)) => {
+                let version = db.version(&key).unwrap_or(1);
+                Ok(Response {
+                    version: Some(version),
+                    ..Response::ok(json!({
+                        "key": key,
+                        "value": String::from_utf8_lossy(value),
+                        "version": version,
+                    }))
+                })
+            }
+            None => Err(DbError::NotFound(key).into()),
+        },
+        ("PUT", Some(key)) => {
+            let ttl = match request.query("ttl") {
+                Some(ttl) => Some(parse_ttl(ttl).map_err(|e| Response::error(400, e))?),
+                None => None,
+            };
+            let force = request.query("force") == Some("true");
+            let version = match precondition(&request)? {
+                Some(expected) => db.compare_and_swap(key.clone(), request.body, &expected, ttl)?,
+                None => {
+                    match ttl {
+                        Some(ttl) => db.insert_with_ttl(key.clone(), request.body, force, ttl)?,
+                        None => db.insert(key.clone(), request.body, force)?,
+                    };
+                    db.version(&key).unwrap_or(1)
+                }
+            };
+            Ok(Response {
+                status: if version == 1 { 201 } else { 200 },
+                version: Some(version),
+                body: json!({ "key": key, "version": version }),
+            })
+        }
+        ("DELETE", Some(key)) => {
+            if let Some(expected) = precondition(&request)? {
+                db.check(&key, &expected)?;
+            }
+            match db.remove(&key)? {
+                Some((key, value)) => Ok(Response::ok(
+                    json!({ "key": key, "value": String::from_utf8_lossy(&value) }),
+                )),
+                None => Err(DbError::NotFound(key).into()),
+            }
+        }
+        _ => Err(Response::error(405, "Method not allowed.")),
+    }
+}
+
+// The condition given by the If-Match or If-None-Match header of a request.
+fn precondition(request: &Request) -> Result<Option<Precondition>, Response> {
+    if let Some(tag) = request.header("If-Match") {
+        if tag == "*" {
+            return Ok(Some(Precondition::Present));
+        }
+        return tag
+            .strip_prefix('"')
+            .and_then(|tag| tag.strip_suffix('"'))
+            .and_then(|version| version.parse().ok())
+            .map(|version| Some(Precondition::Version(version)))
+            .ok_or_else(|| Response::error(400, "If-Match must be * or a single ETag."));
+    }
+
+    match request.header("If-None-Match") {
+        Some("*") => Ok(Some(Precondition::Absent)),
+        Some(_) => Err(Response::error(400, "Only If-None-Match: * is supported.")),
+        None => Ok(None),
+    }
+}
+
+// Parse a query string into its names and values.
+fn parse_query(query: &str) -> Option<Vec<(String, String)>> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let name = String::from_utf8(percent_decode(name, true)?).ok()?;
+            let value = String::from_utf8(percent_decode(value, true)?).ok()?;
+            Some((name, value))
+        })
+        .collect()
+}
+
+// Decode %XX escapes, and '+' as a space in a query string.
+fn percent_decode(s: &str, query: bool) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                decoded.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b'+' if query => decoded.push(b' '),
+            _ => decoded.push(b),
+        }
+    }
+    Some(decoded)
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        505 => "HTTP Version Not Supported",
+        _ => "Internal Server Error",
+    }
+}
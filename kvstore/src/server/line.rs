@@ -0,0 +1,404 @@
+// A simple line protocol for talking to a server. Each request and response
+// is a line of tab separated fields, escaped like the database file so keys
+// and values can hold any bytes:
+//
+//     GET key                 OK value version
+//     SET key value [option]  OK
+//     DEL key                 OK value
+//     LIST [prefix]           OK count, followed by count lines of: key value
+//     NS name                 OK
+//     WATCH key [prefix]      OK, followed by a line for each change
+//
+// SET fails if the key already exists unless given the `force` option, and
+// takes a `ttl=SECONDS` option to make the key expire. NS selects the
+// namespace that the requests after it on the same connection use, which is
+// the default namespace to begin with. WATCH takes a `prefix` option to watch
+// the keys that start with the one given, and keeps the connection to itself
+// to report changes on, each as one of:
+//
+//     set key new [old]
+//     remove key old
+//     expire key old
+//
+// A request that fails is answered with `ERR code message`, where code is the
+// exit code the CLI uses for the error.
+
+use super::Shared;
+use crate::database::format::{self, Version};
+use crate::database::{DEFAULT_NAMESPACE, DbError};
+use crate::watch::{self, Event, Target};
+use std::io::{BufRead, Error, ErrorKind, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// The longest request line accepted, so a client can't use up memory. A
+// request carries a whole value, so this leaves as much room as the RESP
+// front end does for one.
+const MAX_REQUEST_LEN: u64 = 512 * 1024 * 1024;
+
+// A request made to a server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Get(String),
+    Set {
+        key: String,
+        value: Vec<u8>,
+        force: bool,
+        ttl: Option<Duration>,
+    },
+    Del(String),
+    List(Option<String>),
+    Ns(String),
+    Watch(Target),
+}
+
+impl Request {
+    // Parse a request line, without its trailing newline.
+    pub fn parse(line: &str) -> Result<Request, String> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let unescape = |field| format::unescape(field, Version::V2);
+        let command = fields[0].to_ascii_uppercase();
+
+        match (command.as_str(), &fields[1..]) {
+            ("GET", [key]) => Ok(Request::Get(unescape(key)?)),
+            ("SET", [key, value, options @ ..]) => {
+                let (mut force, mut ttl) = (false, None);
+                for option in options {
+                    match option.split_once('=') {
+                        None if *option == "force" => force = true,
+                        Some(("ttl", seconds)) => match seconds.parse() {
+                            Ok(seconds) if seconds > 0 => ttl = Some(Duration::from_secs(seconds)),
+                            _ => return Err(format!("invalid ttl '{}'", seconds)),
+                        },
+                        _ => return Err(format!("unknown option '{}'", option)),
+                    }
+                }
+                Ok(Request::Set {
+                    key: unescape(key)?,
+                    value: format::unescape_bytes(value, Version::V2)?,
+                    force,
+                    ttl,
+                })
+            }
+            ("DEL", [key]) => Ok(Request::Del(unescape(key)?)),
+            ("LIST", []) => Ok(Request::List(None)),
+            ("LIST", [prefix]) => Ok(Request::List(Some(unescape(prefix)?))),
+            ("NS", [name]) => Ok(Request::Ns(unescape(name)?)),
+            ("WATCH", [key]) => Ok(Request::Watch(Target::Key(unescape(key)?))),
+            ("WATCH", [prefix, "prefix"]) => Ok(Request::Watch(Target::Prefix(unescape(prefix)?))),
+            _ => Err(format!("invalid request '{}'", line)),
+        }
+    }
+
+    // The line sent for the request, including its trailing newline.
+    pub fn to_line(&self) -> String {
+        match self {
+            Request::Get(key) => format!("GET\t{}\n", format::escape(key)),
+            Request::Set {
+                key,
+                value,
+                force,
+                ttl,
+            } => {
+                let mut line = format!(
+                    "SET\t{}\t{}",
+                    format::escape(key),
+                    format::escape_bytes(value)
+                );
+                if *force {
+                    line.push_str("\tforce");
+                }
+                if let Some(ttl) = ttl {
+                    line.push_str(&format!("\tttl={}", ttl.as_secs()));
+                }
+                line + "\n"
+            }
+            Request::Del(key) => format!("DEL\t{}\n", format::escape(key)),
+            Request::List(None) => "LIST\n".to_string(),
+            Request::List(Some(prefix)) => format!("LIST\t{}\n", format::escape(prefix)),
+            Request::Ns(name) => format!("NS\t{}\n", format::escape(name)),
+            Request::Watch(Target::Key(key)) => format!("WATCH\t{}\n", format::escape(key)),
+            Request::Watch(Target::Prefix(prefix)) => {
+                format!("WATCH\t{}\tprefix\n", format::escape(prefix))
+            }
+        }
+    }
+}
+
+// Answer the requests read from a connection until it is closed.
+pub fn handle(
+    mut reader: impl BufRead + Send,
+    mut writer: impl Write,
+    db: &Shared,
+) -> std::io::Result<()> {
+    let mut namespace = DEFAULT_NAMESPACE.to_string();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let len = reader
+            .by_ref()
+            .take(MAX_REQUEST_LEN)
+            .read_until(b'\n', &mut line)?;
+        if len == 0 {
+            return Ok(());
+        }
+        if len as u64 == MAX_REQUEST_LEN && !line.ends_with(b"\n") {
+            // The rest of the line can't be told apart from the next request,
+            // so the connection is closed.
+            let e = DbError::Io(Error::new(ErrorKind::InvalidData, "request too long"));
+            writer.write_all(error_line(&e).as_bytes())?;
+            return writer.flush();
+        }
+
+        let request = std::str::from_utf8(&line)
+            .map_err(|_| "invalid UTF-8 in request".to_string())
+            .and_then(|line| Request::parse(line.trim_end_matches(['\n', '\r'])))
+            .map_err(|reason| DbError::Io(Error::new(ErrorKind::InvalidData, reason)));
+        // Watching takes over the connection until it's closed, which a
+        // thread of its own reads for, since a watcher may go a long time
+        // without writing anything that would fail.
+        if let Ok(Request::Watch(target)) = request {
+            let closed = AtomicBool::new(false);
+            return std::thread::scope(|scope| {
+                scope.spawn(|| {
+                    wait_for_close(&mut reader);
+                    closed.store(true, Ordering::Relaxed);
+                });
+                match watch(db, &namespace, &target, &closed, &mut writer) {
+                    Err(DbError::Io(e)) => Err(e),
+                    Err(e) => writer.write_all(error_line(&e).as_bytes()),
+                    Ok(()) => Ok(()),
+                }
+            });
+        }
+        let response = request
+            .and_then(|request| respond(request, &mut namespace, db))
+            .unwrap_or_else(|e| error_line(&e));
+        writer.write_all(response.as_bytes())?;
+        writer.flush()?;
+    }
+}
+
+fn error_line(e: &DbError) -> String {
+    format!(
+        "ERR\t{}\t{}\n",
+        e.exit_code(),
+        format::escape(&e.to_string())
+    )
+}
+
+// Read and ignore whatever a client sends until it closes the connection.
+fn wait_for_close(reader: &mut impl BufRead) {
+    loop {
+        match reader.fill_buf() {
+            Ok([]) => return,
+            Ok(buf) => {
+                let len = buf.len();
+                reader.consume(len);
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(_) => return,
+        }
+    }
+}
+
+// Report changes to the watched keys in a namespace, after an OK response to
+// show the watch has started, until the connection is closed.
+fn watch(
+    db: &Shared,
+    namespace: &str,
+    target: &Target,
+    closed: &AtomicBool,
+    writer: &mut impl Write,
+) -> Result<(), DbError> {
+    let snapshot = || -> Result<watch::Snapshot, DbError> {
+        let mut db = db.read();
+        db.use_namespace(namespace)?;
+        Ok(watch::snapshot(&db, target))
+    };
+
+    let mut seen = db.requests();
+    let mut current = snapshot()?;
+    writer.write_all(b"OK\n")?;
+    writer.flush()?;
+    while !closed.load(Ordering::Relaxed) {
+        seen = db.wait(seen, watch::POLL_INTERVAL);
+        let next = snapshot()?;
+        for event in watch::changes(&current, &next) {
+            writer.write_all(event_line(&event).as_bytes())?;
+        }
+        writer.flush()?;
+        current = next;
+    }
+    Ok(())
+}
+
+fn event_line(event: &Event) -> String {
+    match event {
+        Event::Set { key, old, new } => {
+            let mut line = format!(
+                "set\t{}\t{}",
+                format::escape(key),
+                format::escape_bytes(new)
+            );
+            if let Some(old) = old {
+                line.push_str(&format!("\t{}", format::escape_bytes(old)));
+            }
+            line + "\n"
+        }
+        Event::Remove { key, old } => format!(
+            "remove\t{}\t{}\n",
+            format::escape(key),
+            format::escape_bytes(old)
+        ),
+        Event::Expire { key, old } => format!(
+            "expire\t{}\t{}\n",
+            format::escape(key),
+            format::escape_bytes(old)
+        ),
+    }
+}
+
+// Carry out a request against the database and return the response.
+fn respond(request: Request, namespace: &mut String, db: &Shared) -> Result<String, DbError> {
+    let mut db = db.lock();
+    if !matches!(request, Request::Ns(_)) {
+        db.use_namespace(namespace)?;
+    }
+
+    match request {
+        Request::Get(key) => match db.get(&key) {
+            Some((_, value)) => Ok(format!(
+                "OK\t{}\t{}\n",
+                format::escape_bytes(value),
+                db.version(&key).unwrap_or(1)
+            )),
+            None => Err(DbError::NotFound(key)),
+        },
+        Request::Set {
+            key,
+            value,
+            force,
+            ttl,
+        } => {
+            match ttl {
+                Some(ttl) => db.insert_with_ttl(key, value, force, ttl)?,
+                None => db.insert(key, value, force)?,
+            };
+            Ok("OK\n".to_string())
+        }
+        Request::Del(key) => match db.remove(&key)? {
+            Some((_, value)) => Ok(format!("OK\t{}\n", format::escape_bytes(&value))),
+            None => Err(DbError::NotFound(key)),
+        },
+        Request::List(prefix) => {
+            let entries: Vec<String> = db
+                .iter()
+                .filter(|(k, _)| prefix.as_ref().is_none_or(|p| k.starts_with(p.as_str())))
+                .map(|(k, v)| format!("{}\t{}\n", format::escape(k), format::escape_bytes(v)))
+                .collect();
+            Ok(format!("OK\t{}\n{}", entries.len(), entries.concat()))
+        }
+        Request::Ns(name) => {
+            db.use_namespace(&name)?;
+            *namespace = name;
+            Ok("OK\n".to_string())
+        }
+        // Answered by 'handle' instead, since it keeps the connection.
+        Request::Watch(_) => unreachable!(),
+    }
+}
+
+// Read the response to a request, returning its fields after the leading OK,
+// still escaped. An error response is returned as 'DbError::Remote'.
+pub fn read_response(reader: &mut dyn BufRead) -> Result<Vec<String>, DbError> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "The server closed the connection.",
+        )
+        .into());
+    }
+    let invalid = || Error::new(ErrorKind::InvalidData, "Invalid response from the server.");
+
+    let fields: Vec<String> = line
+        .trim_end_matches('\n')
+        .split('\t')
+        .map(String::from)
+        .collect();
+    match fields.split_first() {
+        Some((status, rest)) if status == "OK" => Ok(rest.to_vec()),
+        Some((status, [code, message])) if status == "ERR" => Err(DbError::Remote {
+            code: code.parse().map_err(|_| invalid())?,
+            message: format::unescape(message, Version::V2).map_err(|_| invalid())?,
+        }),
+        _ => Err(invalid().into()),
+    }
+}
+
+// Read the key/value lines that follow the response to a LIST request.
+pub fn read_entries(
+    reader: &mut dyn BufRead,
+    count: usize,
+) -> Result<Vec<(String, Vec<u8>)>, DbError> {
+    let invalid = || Error::new(ErrorKind::InvalidData, "Invalid response from the server.");
+    // The count comes from the server, so it's only trusted so far.
+    let mut entries = Vec::with_capacity(count.min(1024));
+    for _ in 0..count {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let (key, value) = line
+            .strip_suffix('\n')
+            .and_then(|line| line.split_once('\t'))
+            .ok_or_else(invalid)?;
+        entries.push((
+            format::unescape(key, Version::V2).map_err(|_| invalid())?,
+            format::unescape_bytes(value, Version::V2).map_err(|_| invalid())?,
+        ));
+    }
+
+    Ok(entries)
+}
+
+// Read the next change reported after the response to a WATCH request.
+pub fn read_event(reader: &mut dyn BufRead) -> Result<Event, DbError> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "The server closed the connection.",
+        )
+        .into());
+    }
+    let invalid = || Error::new(ErrorKind::InvalidData, "Invalid response from the server.");
+    let unescape = |field| format::unescape(field, Version::V2).map_err(|_| invalid());
+    let unescape_bytes = |field| format::unescape_bytes(field, Version::V2).map_err(|_| invalid());
+
+    let fields: Vec<&str> = line.trim_end_matches('\n').split('\t').collect();
+    match fields.as_slice() {
+        ["set", key, new] => Ok(Event::Set {
+            key: unescape(key)?,
+            old: None,
+            new: unescape_bytes(new)?,
+        }),
+        ["set", key, new, old] => Ok(Event::Set {
+            key: unescape(key)?,
+            old: Some(unescape_bytes(old)?),
+            new: unescape_bytes(new)?,
+        }),
+        ["remove", key, old] => Ok(Event::Remove {
+            key: unescape(key)?,
+            old: unescape_bytes(old)?,
+        }),
+        ["expire", key, old] => Ok(Event::Expire {
+            key: unescape(key)?,
+            old: unescape_bytes(old)?,
+        }),
+        ["ERR", code, message] => Err(DbError::Remote {
+            code: code.parse().map_err(|_| invalid())?,
+            message: unescape(message)?,
+        }),
+        _ => Err(invalid().into()),
+    }
+}
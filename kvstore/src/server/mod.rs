@@ -0,0 +1,268 @@
+// Server mode, where a single process keeps the database open and clients
+// send it requests over a socket instead of each opening the database files
+// themselves.
+
+use crate::database::Database;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::ops::{Deref, DerefMut};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+mod http;
+pub mod line;
+mod resp;
+
+// Where a server listens for connections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    // A Unix domain socket at a path.
+    Unix(PathBuf),
+    // A TCP host and port.
+    Tcp(String),
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("unix", path)) if !path.is_empty() => Ok(Address::Unix(PathBuf::from(path))),
+            Some(("tcp", host_port)) if host_port.rsplit_once(':').is_some() => {
+                Ok(Address::Tcp(host_port.to_string()))
+            }
+            _ => Err(format!(
+                "Invalid address '{}'; expected unix:PATH or tcp:HOST:PORT.",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Unix(path) => write!(f, "unix:{}", path.display()),
+            Address::Tcp(host_port) => write!(f, "tcp:{}", host_port),
+        }
+    }
+}
+
+// The protocol a server speaks to its clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    // The line protocol that `--remote` uses.
+    Line,
+    // The Redis serialization protocol.
+    Resp,
+    // A REST API over HTTP, with JSON responses.
+    Http,
+}
+
+impl Protocol {
+    pub const NAMES: [&'static str; 3] = ["line", "resp", "http"];
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "line" => Ok(Protocol::Line),
+            "resp" => Ok(Protocol::Resp),
+            "http" => Ok(Protocol::Http),
+            _ => Err(format!("Unknown protocol '{}'.", s)),
+        }
+    }
+}
+
+// The database a server shares between its connections, along with a count
+// of the requests made against it so watchers can wait for changes.
+pub struct Shared {
+    db: Mutex<Database>,
+    requests: Mutex<u64>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn new(db: Database) -> Shared {
+        Shared {
+            db: Mutex::new(db),
+            requests: Mutex::new(0),
+            changed: Condvar::new(),
+        }
+    }
+
+    // Lock the database to carry out a request that may change it. Watchers
+    // are woken once the lock is released.
+    pub fn lock(&self) -> Locked<'_> {
+        Locked {
+            db: self.read(),
+            shared: self,
+        }
+    }
+
+    // Lock the database only to read from it, without waking watchers.
+    // Requests don't leave the database half changed if they fail, so it's
+    // still usable after a panic on another connection.
+    pub fn read(&self) -> MutexGuard<'_, Database> {
+        self.db.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // The number of requests made so far, to pass to 'wait'.
+    pub fn requests(&self) -> u64 {
+        *self.requests.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Wait for a request to be made after the first 'seen', or for the
+    // timeout to pass. Returns the number of requests made so far.
+    pub fn wait(&self, seen: u64, timeout: Duration) -> u64 {
+        let requests = self.requests.lock().unwrap_or_else(PoisonError::into_inner);
+        let (requests, _) = self
+            .changed
+            .wait_timeout_while(requests, timeout, |requests| *requests == seen)
+            .unwrap_or_else(PoisonError::into_inner);
+        *requests
+    }
+}
+
+// The database, locked for a request.
+pub struct Locked<'a> {
+    db: MutexGuard<'a, Database>,
+    shared: &'a Shared,
+}
+
+impl Deref for Locked<'_> {
+    type Target = Database;
+
+    fn deref(&self) -> &Database {
+        &self.db
+    }
+}
+
+impl DerefMut for Locked<'_> {
+    fn deref_mut(&mut self) -> &mut Database {
+        &mut self.db
+    }
+}
+
+impl Drop for Locked<'_> {
+    fn drop(&mut self) {
+        *self
+            .shared
+            .requests
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) += 1;
+        self.shared.changed.notify_all();
+    }
+}
+
+// The two halves of a connection, for reading requests or responses a line
+// at a time and writing them back.
+pub type Connection = (Box<dyn BufRead + Send>, Box<dyn Write + Send>);
+
+// Connect to a server.
+pub fn connect(address: &Address) -> std::io::Result<Connection> {
+    match address {
+        #[cfg(unix)]
+        Address::Unix(path) => {
+            let stream = UnixStream::connect(path)?;
+            Ok((
+                Box::new(BufReader::new(stream.try_clone()?)),
+                Box::new(stream),
+            ))
+        }
+        #[cfg(not(unix))]
+        Address::Unix(_) => Err(unix_unsupported()),
+        Address::Tcp(host_port) => {
+            let stream = TcpStream::connect(host_port)?;
+            Ok((
+                Box::new(BufReader::new(stream.try_clone()?)),
+                Box::new(stream),
+            ))
+        }
+    }
+}
+
+// Serve requests against the database until the process is stopped. Each
+// connection is handled on its own thread, one request at a time, while the
+// database itself handles a single request at a time from any connection.
+// Once listening, the address is printed to stdout, with the port the system
+// picked for a TCP port of 0.
+pub fn serve(db: Database, address: &Address, protocol: Protocol) -> std::io::Result<()> {
+    let db = Arc::new(Shared::new(db));
+    match address {
+        #[cfg(unix)]
+        Address::Unix(path) => {
+            remove_stale_socket(path)?;
+            let listener = UnixListener::bind(path)?;
+            println!("Listening on {}", address);
+            for stream in listener.incoming() {
+                let stream = stream?;
+                let reader = BufReader::new(stream.try_clone()?);
+                spawn_connection(protocol, reader, stream, &db);
+            }
+        }
+        #[cfg(not(unix))]
+        Address::Unix(_) => return Err(unix_unsupported()),
+        Address::Tcp(host_port) => {
+            let listener = TcpListener::bind(host_port)?;
+            println!("Listening on tcp:{}", listener.local_addr()?);
+            for stream in listener.incoming() {
+                let stream = stream?;
+                let reader = BufReader::new(stream.try_clone()?);
+                spawn_connection(protocol, reader, stream, &db);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn spawn_connection<R, W>(protocol: Protocol, reader: R, writer: W, db: &Arc<Shared>)
+where
+    R: BufRead + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let db = Arc::clone(db);
+    std::thread::spawn(move || {
+        // A client going away part way through is its own problem; the
+        // server carries on with the others.
+        let result = match protocol {
+            Protocol::Line => line::handle(reader, writer, &db),
+            Protocol::Resp => resp::handle(reader, writer, &db),
+            Protocol::Http => http::handle(reader, writer, &db),
+        };
+        if let Err(e) = result {
+            eprintln!("Connection failed: {}", e);
+        }
+    });
+}
+
+// A socket file left behind by a server that has stopped would keep a new
+// one from binding to the same path. Only a socket that nothing is listening
+// on is removed, never any other kind of file.
+#[cfg(unix)]
+fn remove_stale_socket(path: &std::path::Path) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() && UnixStream::connect(path).is_err() => {
+            std::fs::remove_file(path)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn unix_unsupported() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Unix domain sockets aren't supported on this platform.",
+    )
+}
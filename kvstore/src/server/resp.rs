@@ -0,0 +1,365 @@
+// A front end speaking the Redis serialization protocol (RESP2), so existing
+// Redis clients can use a server. Requests are arrays of bulk strings, or
+// inline commands of whitespace separated words, and the commands supported
+// are:
+//
+//     PING [message]
+//     GET key
+//     SET key value [NX | XX] [EX seconds]
+//     DEL key [key ...]
+//     EXISTS key [key ...]
+//     KEYS pattern
+//     SCAN cursor [MATCH pattern] [COUNT count]
+//     INCR key
+//     EXPIRE key seconds
+//     TTL key
+//
+// Patterns match keys like `list --glob`. Every connection uses the default
+// namespace. SCAN cursors count the keys scanned so far in key order.
+
+use super::Shared;
+use crate::database::{Database, DbError};
+use crate::glob_match;
+use std::io::{BufRead, Read, Write};
+use std::time::Duration;
+
+// Keys returned by a SCAN that isn't given a COUNT.
+const SCAN_COUNT: usize = 10;
+
+// The longest bulk string a command may carry, like Redis's default
+// proto-max-bulk-len.
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+// The longest inline command or header line, like Redis's limit on inline
+// requests, so a client that never ends a line can't use up memory.
+const MAX_LINE_LEN: u64 = 64 * 1024;
+
+// A reply to a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Reply {
+    Status(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    fn bulk(value: impl Into<Vec<u8>>) -> Reply {
+        Reply::Bulk(Some(value.into()))
+    }
+
+    fn write_to(&self, out: &mut Vec<u8>) {
+        match self {
+            Reply::Status(status) => out.extend_from_slice(format!("+{}\r\n", status).as_bytes()),
+            Reply::Error(message) => out.extend_from_slice(format!("-{}\r\n", message).as_bytes()),
+            Reply::Integer(n) => out.extend_from_slice(format!(":{}\r\n", n).as_bytes()),
+            Reply::Bulk(None) => out.extend_from_slice(b"$-1\r\n"),
+            Reply::Bulk(Some(value)) => {
+                out.extend_from_slice(format!("${}\r\n", value.len()).as_bytes());
+                out.extend_from_slice(value);
+                out.extend_from_slice(b"\r\n");
+            }
+            Reply::Array(replies) => {
+                out.extend_from_slice(format!("*{}\r\n", replies.len()).as_bytes());
+                for reply in replies {
+                    reply.write_to(out);
+                }
+            }
+        }
+    }
+}
+
+impl From<DbError> for Reply {
+    fn from(e: DbError) -> Self {
+        Reply::Error(format!("ERR {}", e))
+    }
+}
+
+// Answer the commands read from a connection until it is closed, or until it
+// sends something that isn't valid RESP.
+pub fn handle(
+    mut reader: impl BufRead,
+    mut writer: impl Write,
+    db: &Shared,
+) -> std::io::Result<()> {
+    loop {
+        let (reply, done) = match read_command(&mut reader)? {
+            None => return Ok(()),
+            Some(Ok(args)) if args.is_empty() => continue,
+            Some(Ok(args)) => (execute(args, db), false),
+            Some(Err(reason)) => (
+                Reply::Error(format!("ERR Protocol error: {}", reason)),
+                true,
+            ),
+        };
+
+        let mut out = Vec::new();
+        reply.write_to(&mut out);
+        writer.write_all(&out)?;
+        writer.flush()?;
+        if done {
+            return Ok(());
+        }
+    }
+}
+
+// Read the arguments of the next command. Returns None at the end of the
+// connection.
+fn read_command(
+    reader: &mut impl BufRead,
+) -> std::io::Result<Option<Result<Vec<Vec<u8>>, String>>> {
+    let line = match read_line(reader)? {
+        None => return Ok(None),
+        Some(Err(reason)) => return Ok(Some(Err(reason))),
+        Some(Ok(line)) => line,
+    };
+    let Some(count) = line.strip_prefix(b"*") else {
+        // An inline command, as typed into a terminal.
+        let words = line
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|word| !word.is_empty())
+            .map(<[u8]>::to_vec)
+            .collect();
+        return Ok(Some(Ok(words)));
+    };
+
+    let Some(count) = parse_length(count) else {
+        return Ok(Some(Err("invalid multibulk length".to_string())));
+    };
+    let mut args = Vec::with_capacity(count.min(1024));
+    for _ in 0..count {
+        let line = match read_line(reader)? {
+            None => return Ok(None),
+            Some(Err(reason)) => return Ok(Some(Err(reason))),
+            Some(Ok(line)) => line,
+        };
+        let Some(len) = line.strip_prefix(b"$").and_then(parse_length) else {
+            return Ok(Some(Err("expected a bulk string".to_string())));
+        };
+        if len > MAX_BULK_LEN {
+            return Ok(Some(Err("invalid bulk length".to_string())));
+        }
+        // Only take as much memory as the client actually sends.
+        let mut arg = Vec::new();
+        reader.take(len as u64 + 2).read_to_end(&mut arg)?;
+        if arg.len() < len + 2 {
+            return Ok(None);
+        }
+        if !arg.ends_with(b"\r\n") {
+            return Ok(Some(Err("bulk string isn't followed by CRLF".to_string())));
+        }
+        arg.truncate(len);
+        args.push(arg);
+    }
+
+    Ok(Some(Ok(args)))
+}
+
+// Read a line without its line ending. Returns None at the end of the
+// connection, or the reason it can't be read if it's too long.
+fn read_line(reader: &mut impl BufRead) -> std::io::Result<Option<Result<Vec<u8>, String>>> {
+    let mut line = Vec::new();
+    let read = reader.take(MAX_LINE_LEN).read_until(b'\n', &mut line)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if read as u64 == MAX_LINE_LEN && !line.ends_with(b"\n") {
+        return Ok(Some(Err("too big inline request".to_string())));
+    }
+    let len = line
+        .strip_suffix(b"\n")
+        .map_or(line.len(), |l| l.strip_suffix(b"\r").unwrap_or(l).len());
+    line.truncate(len);
+    Ok(Some(Ok(line)))
+}
+
+fn parse_length(digits: &[u8]) -> Option<usize> {
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+// Carry out a command against the database.
+fn execute(args: Vec<Vec<u8>>, db: &Shared) -> Reply {
+    let name = String::from_utf8_lossy(&args[0]).to_ascii_lowercase();
+    let mut args = args.into_iter().skip(1);
+    let mut db = db.lock();
+
+    let result = match (name.as_str(), args.len()) {
+        ("ping", 0) => Ok(Reply::Status("PONG")),
+        ("ping", 1) => Ok(Reply::Bulk(args.next())),
+        ("get", 1) => {
+            key(args.next()).map(|key| Reply::Bulk(db.get(&key).map(|(_, v)| v.to_vec())))
+        }
+        ("set", 2..) => set(&mut db, args.collect()),
+        // Every key is checked before any is removed, and they're all removed
+        // at once, so a failure leaves them all in place.
+        ("del", 1..) => args
+            .map(|arg| key(Some(arg)))
+            .collect::<Result<Vec<_>, _>>()
+            .and_then(|keys| {
+                Ok(db.transaction(|tx| {
+                    Ok(keys.iter().filter(|key| tx.remove(key).is_some()).count())
+                })?)
+            })
+            .map(|removed| Reply::Integer(removed as i64)),
+        ("exists", 1..) => args
+            .map(|arg| Ok(i64::from(db.get(&key(Some(arg))?).is_some())))
+            .sum::<Result<i64, Reply>>()
+            .map(Reply::Integer),
+        ("keys", 1) => pattern(args.next()).map(|pattern| {
+            Reply::Array(
+                db.iter()
+                    .filter(|(k, _)| glob_match(&pattern, k))
+                    .map(|(k, _)| Reply::bulk(k.as_bytes()))
+                    .collect(),
+            )
+        }),
+        ("scan", 1..) => scan(&db, args.collect()),
+        ("incr", 1) => key(args.next()).and_then(|key| incr(&mut db, key)),
+        ("expire", 2) => key(args.next()).and_then(|key| {
+            let seconds = integer(args.next())?;
+            expire(&mut db, key, seconds)
+        }),
+        ("ttl", 1) => key(args.next()).map(|key| match (db.get(&key), db.ttl(&key)) {
+            (None, _) => Reply::Integer(-2),
+            (Some(_), None) => Reply::Integer(-1),
+            (Some(_), Some(ttl)) => Reply::Integer(ttl.as_secs_f64().round() as i64),
+        }),
+        (
+            "ping" | "get" | "set" | "del" | "exists" | "keys" | "scan" | "incr" | "expire" | "ttl",
+            _,
+        ) => Err(Reply::Error(format!(
+            "ERR wrong number of arguments for '{}' command",
+            name
+        ))),
+        _ => Err(Reply::Error(format!("ERR unknown command '{}'", name))),
+    };
+
+    result.unwrap_or_else(|reply| reply)
+}
+
+// SET key value [NX | XX] [EX seconds]
+fn set(db: &mut Database, args: Vec<Vec<u8>>) -> Result<Reply, Reply> {
+    let mut args = args.into_iter();
+    let key = key(args.next())?;
+    let value = args.next().unwrap_or_default();
+
+    let syntax_error = || Reply::Error("ERR syntax error".to_string());
+    let (mut only_absent, mut only_present, mut ttl) = (false, false, None);
+    while let Some(option) = args.next() {
+        match option.to_ascii_uppercase().as_slice() {
+            b"NX" if !only_present => only_absent = true,
+            b"XX" if !only_absent => only_present = true,
+            b"EX" if ttl.is_none() => match integer(args.next())? {
+                seconds if seconds > 0 => ttl = Some(Duration::from_secs(seconds as u64)),
+                _ => {
+                    return Err(Reply::Error(
+                        "ERR invalid expire time in 'set' command".to_string(),
+                    ));
+                }
+            },
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    let exists = db.get(&key).is_some();
+    if only_absent && exists || only_present && !exists {
+        return Ok(Reply::Bulk(None));
+    }
+    match ttl {
+        Some(ttl) => db.insert_with_ttl(key, value, true, ttl)?,
+        None => db.insert(key, value, true)?,
+    };
+
+    Ok(Reply::Status("OK"))
+}
+
+// SCAN cursor [MATCH pattern] [COUNT count]
+fn scan(db: &Database, args: Vec<Vec<u8>>) -> Result<Reply, Reply> {
+    let mut args = args.into_iter();
+    let cursor = integer(args.next())?;
+    let mut pattern = None;
+    let mut count = SCAN_COUNT;
+    while let Some(option) = args.next() {
+        match option.to_ascii_uppercase().as_slice() {
+            b"MATCH" => pattern = Some(self::pattern(args.next())?),
+            b"COUNT" => match integer(args.next())? {
+                n if n > 0 => count = n as usize,
+                _ => return Err(Reply::Error("ERR syntax error".to_string())),
+            },
+            _ => return Err(Reply::Error("ERR syntax error".to_string())),
+        }
+    }
+    let Ok(cursor) = usize::try_from(cursor) else {
+        return Err(Reply::Error("ERR invalid cursor".to_string()));
+    };
+
+    let keys: Vec<&String> = db.iter().map(|(k, _)| k).collect();
+    let end = cursor.saturating_add(count).min(keys.len());
+    let next = if end < keys.len() { end } else { 0 };
+    let found = keys
+        .get(cursor..end)
+        .unwrap_or_default()
+        .iter()
+        .filter(|k| pattern.as_ref().is_none_or(|p| glob_match(p, k)))
+        .map(|k| Reply::bulk(k.as_bytes()))
+        .collect();
+
+    Ok(Reply::Array(vec![
+        Reply::bulk(next.to_string()),
+        Reply::Array(found),
+    ]))
+}
+
+// INCR key. Like Redis, a key that doesn't exist counts as 0, and the key
+// keeps its time to live.
+fn incr(db: &mut Database, key: String) -> Result<Reply, Reply> {
+    let current = match db.get(&key) {
+        Some((_, value)) => std::str::from_utf8(value)
+            .ok()
+            .and_then(|value| value.parse::<i64>().ok())
+            .ok_or_else(|| {
+                Reply::Error("ERR value is not an integer or out of range".to_string())
+            })?,
+        None => 0,
+    };
+    let Some(next) = current.checked_add(1) else {
+        return Err(Reply::Error(
+            "ERR increment or decrement would overflow".to_string(),
+        ));
+    };
+
+    let value = next.to_string().into_bytes();
+    match db.ttl(&key) {
+        Some(ttl) if !ttl.is_zero() => db.insert_with_ttl(key, value, true, ttl)?,
+        _ => db.insert(key, value, true)?,
+    };
+
+    Ok(Reply::Integer(next))
+}
+
+// EXPIRE key seconds. A time that isn't positive removes the key right away.
+fn expire(db: &mut Database, key: String, seconds: i64) -> Result<Reply, Reply> {
+    let expired = match seconds {
+        1.. => db.expire(&key, Duration::from_secs(seconds as u64))?,
+        _ => db.remove(&key)?.is_some(),
+    };
+
+    Ok(Reply::Integer(expired.into()))
+}
+
+fn key(arg: Option<Vec<u8>>) -> Result<String, Reply> {
+    String::from_utf8(arg.unwrap_or_default())
+        .map_err(|_| Reply::Error("ERR keys must be valid UTF-8".to_string()))
+}
+
+fn pattern(arg: Option<Vec<u8>>) -> Result<String, Reply> {
+    String::from_utf8(arg.unwrap_or_default())
+        .map_err(|_| Reply::Error("ERR patterns must be valid UTF-8".to_string()))
+}
+
+fn integer(arg: Option<Vec<u8>>) -> Result<i64, Reply> {
+    arg.and_then(|arg| String::from_utf8(arg).ok())
+        .and_then(|arg| arg.parse().ok())
+        .ok_or_else(|| Reply::Error("ERR value is not an integer or out of range".to_string()))
+}
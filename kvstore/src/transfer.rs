@@ -0,0 +1,273 @@
+use crate::database::format::{self, Version};
+use serde::de::{Deserialize, Deserializer, MapAccess, Visitor};
+use serde_json::{Map, Value, json};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+// File formats key/value pairs can be imported from and exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    // `key<TAB>value` lines, escaped like the database file. The only format
+    // that can hold binary values.
+    Tsv,
+    // A single JSON object mapping keys to values.
+    Json,
+    // A `{"key": ..., "value": ...}` JSON object per line.
+    Jsonl,
+    // `KEY=value` lines as used by .env files.
+    Env,
+}
+
+impl Format {
+    pub const NAMES: [&'static str; 4] = ["tsv", "json", "jsonl", "env"];
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tsv" => Ok(Format::Tsv),
+            "json" => Ok(Format::Json),
+            "jsonl" => Ok(Format::Jsonl),
+            "env" => Ok(Format::Env),
+            _ => Err(format!("Unknown import/export format '{}'.", s)),
+        }
+    }
+}
+
+// Parse key/value pairs from the contents of a file in the given format.
+// A key that's given more than once is an error rather than letting the
+// last value win.
+pub fn parse(format: Format, contents: &str) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let mut pairs = Vec::new();
+    let mut seen = BTreeSet::new();
+    match format {
+        Format::Json => {
+            let Members(members) =
+                serde_json::from_str(contents).map_err(|e| format!("Invalid JSON: {}", e))?;
+            for (key, value) in members {
+                if !seen.insert(key.clone()) {
+                    return Err(format!("duplicate key '{}'", key));
+                }
+                let value = json_value(&key, value)?;
+                pairs.push((key, value.into_bytes()));
+            }
+        }
+        _ => {
+            let lines = contents
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty())
+                .filter(|(_, line)| format != Format::Env || !line.trim_start().starts_with('#'));
+            for (i, line) in lines {
+                let (key, value) =
+                    parse_line(format, line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+                if !seen.insert(key.clone()) {
+                    return Err(format!("line {}: duplicate key '{}'", i + 1, key));
+                }
+                pairs.push((key, value));
+            }
+        }
+    }
+    Ok(pairs)
+}
+
+// The members of a JSON object in the order they're given. Unlike a 'Map',
+// a key that's given twice is kept twice, so it can be caught.
+struct Members(Vec<(String, Value)>);
+
+impl<'de> Deserialize<'de> for Members {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MembersVisitor;
+
+        impl<'de> Visitor<'de> for MembersVisitor {
+            type Value = Members;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a JSON object")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Members, A::Error> {
+                let mut members = Vec::new();
+                while let Some(member) = map.next_entry()? {
+                    members.push(member);
+                }
+                Ok(Members(members))
+            }
+        }
+
+        deserializer.deserialize_map(MembersVisitor)
+    }
+}
+
+// Parse a single line of a line based format.
+fn parse_line(format: Format, line: &str) -> Result<(String, Vec<u8>), String> {
+    match format {
+        Format::Tsv => match line.split('\t').collect::<Vec<_>>().as_slice() {
+            [key, value] => Ok((
+                format::unescape(key, Version::V2)?,
+                format::unescape_bytes(value, Version::V2)?,
+            )),
+            _ => Err("expected a key and a value separated by a tab".to_string()),
+        },
+        Format::Jsonl => {
+            let mut object: Map<String, Value> =
+                serde_json::from_str(line).map_err(|e| format!("invalid JSON: {}", e))?;
+            match (object.remove("key"), object.remove("value")) {
+                (Some(Value::String(key)), Some(value)) => {
+                    let value = json_value(&key, value)?;
+                    Ok((key, value.into_bytes()))
+                }
+                _ => Err("expected an object with \"key\" and \"value\"".to_string()),
+            }
+        }
+        Format::Env => {
+            let line = line.trim();
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            match line.split_once('=') {
+                Some((key, value)) if is_env_name(key.trim()) => Ok((
+                    key.trim().to_string(),
+                    env_value(value.trim())?.into_bytes(),
+                )),
+                _ => Err("expected NAME=value".to_string()),
+            }
+        }
+        Format::Json => unreachable!("JSON isn't a line based format"),
+    }
+}
+
+// Values are stored as strings, so JSON numbers and booleans are kept as
+// their JSON text. Anything else can't be stored.
+fn json_value(key: &str, value: Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s),
+        Value::Number(_) | Value::Bool(_) => Ok(value.to_string()),
+        _ => Err(format!("the value of '{}' must be a string", key)),
+    }
+}
+
+// Parse a .env value, which may be single quoted (taken literally), double
+// quoted (with backslash escapes) or bare.
+fn env_value(value: &str) -> Result<String, String> {
+    if let Some(quoted) = value.strip_prefix('\'') {
+        return match quoted.strip_suffix('\'') {
+            Some(literal) => Ok(literal.to_string()),
+            None => Err("missing closing quote".to_string()),
+        };
+    }
+    let Some(quoted) = value.strip_prefix('"') else {
+        return Ok(value.to_string());
+    };
+    let Some(quoted) = quoted.strip_suffix('"') else {
+        return Err("missing closing quote".to_string());
+    };
+
+    let mut unescaped = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            Some(c @ ('\\' | '"' | '$' | '`')) => unescaped.push(c),
+            _ => return Err(format!("invalid escape sequence in {}", value)),
+        }
+    }
+    Ok(unescaped)
+}
+
+// Quote a value so 'env_value' reads it back unchanged.
+fn env_quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '\\' | '"' | '$' | '`' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Whether a key can be used as an environment variable name.
+fn is_env_name(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Write key/value pairs in the given format.
+pub fn write<'a>(
+    format: Format,
+    out: &mut impl Write,
+    entries: impl Iterator<Item = (&'a String, &'a [u8])>,
+) -> io::Result<()> {
+    match format {
+        Format::Json => {
+            let object: Map<String, Value> = text_entries(entries)?
+                .into_iter()
+                .map(|(k, v)| (k.clone(), json!(v)))
+                .collect();
+            writeln!(out, "{}", serde_json::to_string_pretty(&object)?)?;
+        }
+        Format::Jsonl => {
+            for (k, v) in text_entries(entries)? {
+                writeln!(out, "{}", json!({ "key": k, "value": v }))?;
+            }
+        }
+        Format::Tsv => {
+            for (k, v) in entries {
+                writeln!(out, "{}\t{}", format::escape(k), format::escape_bytes(v))?;
+            }
+        }
+        Format::Env => {
+            // Check every key up front so nothing is written if one is unusable.
+            let entries = text_entries(entries)?;
+            if let Some((k, _)) = entries.iter().find(|(k, _)| !is_env_name(k)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("'{}' can't be used as a .env variable name.", k),
+                ));
+            }
+            for (k, v) in entries {
+                writeln!(out, "{}={}", k, env_quote(v))?;
+            }
+        }
+    }
+    out.flush()
+}
+
+// Collect key/value pairs for a format that can only hold text, failing
+// before anything is written if a value is binary.
+fn text_entries<'a>(
+    entries: impl Iterator<Item = (&'a String, &'a [u8])>,
+) -> io::Result<Vec<(&'a String, &'a str)>> {
+    entries
+        .map(|(k, v)| match std::str::from_utf8(v) {
+            Ok(v) => Ok((k, v)),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The value of '{}' is binary and can only be exported as tsv.",
+                    k
+                ),
+            )),
+        })
+        .collect()
+}
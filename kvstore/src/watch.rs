@@ -0,0 +1,162 @@
+// Watching keys for changes. Changes are found by comparing snapshots of the
+// keys being watched, taken whenever the database may have changed: when its
+// files on disk do, or when a request is made to a server.
+
+use crate::database::{BackendKind, Database, DbError};
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::{Duration, Instant, SystemTime};
+
+// How often to check for changes that nothing announces, such as a key
+// expiring or the database files being written by another process.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// The keys being watched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Key(String),
+    Prefix(String),
+}
+
+// A change to a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    // The key was set, and had the old value before if it existed.
+    Set {
+        key: String,
+        old: Option<Vec<u8>>,
+        new: Vec<u8>,
+    },
+    // The key was removed.
+    Remove {
+        key: String,
+        old: Vec<u8>,
+    },
+    // The key reached the end of its time to live.
+    Expire {
+        key: String,
+        old: Vec<u8>,
+    },
+}
+
+// The values of the keys being watched, along with when they expire and
+// their versions, so setting a key to the same value still counts as a
+// change.
+pub type Snapshot = BTreeMap<String, (Vec<u8>, Option<Instant>, u64)>;
+
+// Take a snapshot of the watched keys in the selected namespace. Only the
+// watched keys are looked at, so watching one key stays cheap however many
+// others there are.
+pub fn snapshot(db: &Database, target: &Target) -> Snapshot {
+    let now = Instant::now();
+    let entries: Box<dyn Iterator<Item = (&String, &[u8])>> = match target {
+        Target::Key(key) => Box::new(db.get(key).into_iter()),
+        Target::Prefix(prefix) => Box::new(
+            db.range(prefix.as_str()..)
+                .take_while(|(k, _)| k.starts_with(prefix.as_str())),
+        ),
+    };
+    entries
+        .map(|(k, v)| {
+            let expires_at = db.ttl(k).map(|ttl| now + ttl);
+            (
+                k.clone(),
+                (v.to_vec(), expires_at, db.version(k).unwrap_or(1)),
+            )
+        })
+        .collect()
+}
+
+// A snapshot without the keys that have expired since it was taken.
+pub fn without_expired(snapshot: &Snapshot) -> Snapshot {
+    let now = Instant::now();
+    snapshot
+        .iter()
+        .filter(|(_, (_, expires_at, _))| expires_at.is_none_or(|at| at > now))
+        .map(|(k, entry)| (k.clone(), entry.clone()))
+        .collect()
+}
+
+// The changes that turn one snapshot into another, in key order. A key that
+// is gone after its time to live ran out is taken to have expired, even if
+// it was removed at about the same time.
+pub fn changes(old: &Snapshot, new: &Snapshot) -> Vec<Event> {
+    let now = Instant::now();
+    let mut events = Vec::new();
+    for (key, (old_value, expires_at, _)) in old {
+        if !new.contains_key(key) {
+            let key = key.clone();
+            let old = old_value.clone();
+            events.push(match expires_at {
+                Some(at) if *at <= now => Event::Expire { key, old },
+                _ => Event::Remove { key, old },
+            });
+        }
+    }
+    for (key, (new_value, _, version)) in new {
+        let old = old.get(key);
+        if old.is_none_or(|(_, _, old_version)| old_version != version) {
+            events.push(Event::Set {
+                key: key.clone(),
+                old: old.map(|(value, _, _)| value.clone()),
+                new: new_value.clone(),
+            });
+        }
+    }
+    events.sort_by(|a, b| event_key(a).cmp(event_key(b)));
+    events
+}
+
+fn event_key(event: &Event) -> &str {
+    match event {
+        Event::Set { key, .. } | Event::Remove { key, .. } | Event::Expire { key, .. } => key,
+    }
+}
+
+// Watch the database files for changes until 'report' fails, polling them
+// since there's no portable way to be notified. The database is read again
+// only once its files have changed.
+pub fn watch_files(
+    path: &str,
+    backend: BackendKind,
+    namespace: &str,
+    passphrase: Option<&[u8]>,
+    target: &Target,
+    mut report: impl FnMut(&Event) -> Result<(), DbError>,
+) -> Result<(), DbError> {
+    let load = || -> Result<Snapshot, DbError> {
+        let mut db = Database::open_read_only(path, backend, true, passphrase)?;
+        db.use_namespace(namespace)?;
+        Ok(snapshot(&db, target))
+    };
+
+    let mut stamp = file_stamp(path)?;
+    let mut current = load()?;
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        let next_stamp = file_stamp(path)?;
+        let next = if next_stamp != stamp {
+            stamp = next_stamp;
+            load()?
+        } else {
+            without_expired(&current)
+        };
+        for event in changes(&current, &next) {
+            report(&event)?;
+        }
+        current = next;
+    }
+}
+
+// When the database file and the write-ahead log of the TSV backend were last
+// modified, and their sizes, or None for a file that doesn't exist.
+type Stamp = [Option<(SystemTime, u64)>; 2];
+
+fn file_stamp(path: &str) -> std::io::Result<Stamp> {
+    let stamp = |path: &str| match fs::metadata(path) {
+        Ok(metadata) => Ok(Some((metadata.modified()?, metadata.len()))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    };
+    Ok([stamp(path)?, stamp(&format!("{}.log", path))?])
+}
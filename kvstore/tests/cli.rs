@@ -1,9 +1,114 @@
 use assert_cmd::Command;
 use predicates::prelude::*;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
 
 type TestResult = Result<(), Box<dyn std::error::Error>>;
 
 const PRG: &str = "kvstore";
+const HEADER: &str = "#kvstore v2\n";
+
+// Build a command that uses a database file inside the given directory, so
+// each test gets its own database.
+fn kvstore(dir: &TempDir) -> Result<Command, Box<dyn std::error::Error>> {
+    let mut cmd = Command::cargo_bin(PRG)?;
+    cmd.current_dir(dir.path()).env("KVSTORE_DB", "kv.db");
+    Ok(cmd)
+}
+
+// Read a database file with the times that values were updated left out,
+// since they change from run to run.
+fn read_without_update_times(path: std::path::PathBuf) -> std::io::Result<String> {
+    let contents = fs::read_to_string(path)?;
+    let lines: Vec<String> = contents
+        .split('\n')
+        .map(|line| {
+            line.split('\t')
+                .filter(|field| !field.starts_with("updated="))
+                .collect::<Vec<_>>()
+                .join("\t")
+        })
+        .collect();
+    Ok(lines.join("\n"))
+}
+
+// A server started with `kvstore serve` on the database in a directory, which
+// is stopped once dropped.
+struct Server {
+    child: process::Child,
+    address: String,
+}
+
+impl Server {
+    fn start(dir: &TempDir, args: &[&str]) -> Result<Server, Box<dyn std::error::Error>> {
+        let mut child = process::Command::new(assert_cmd::cargo::cargo_bin(PRG))
+            .current_dir(dir.path())
+            .env("KVSTORE_DB", "kv.db")
+            .arg("serve")
+            .args(args)
+            .stdout(process::Stdio::piped())
+            .spawn()?;
+
+        // The server prints where it's listening once it's ready.
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap()).read_line(&mut line)?;
+        let address = line
+            .trim_end()
+            .strip_prefix("Listening on ")
+            .ok_or(format!("unexpected output '{}'", line))?
+            .to_string();
+
+        Ok(Server { child, address })
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// A `kvstore watch` running on the database in a directory, with the changes
+// it prints read a line at a time. It's stopped once dropped.
+struct Watcher {
+    child: process::Child,
+    stdout: BufReader<process::ChildStdout>,
+}
+
+impl Watcher {
+    fn start(dir: &TempDir, args: &[&str]) -> Result<Watcher, Box<dyn std::error::Error>> {
+        let mut child = process::Command::new(assert_cmd::cargo::cargo_bin(PRG))
+            .current_dir(dir.path())
+            .env("KVSTORE_DB", "kv.db")
+            .args(args)
+            .stdout(process::Stdio::piped())
+            .spawn()?;
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+
+        // Nothing is printed until something changes, so give it time to
+        // read the database first.
+        thread::sleep(Duration::from_millis(500));
+        Ok(Watcher { child, stdout })
+    }
+
+    fn next_line(&mut self) -> std::io::Result<String> {
+        let mut line = String::new();
+        self.stdout.read_line(&mut line)?;
+        Ok(line)
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
 
 #[test]
 fn no_args_will_show_usage() -> TestResult {
@@ -16,18 +121,19 @@ fn no_args_will_show_usage() -> TestResult {
 
 #[test]
 fn init_set_get() -> TestResult {
+    let dir = TempDir::new()?;
     let init_args = ["init"];
     let set_args = ["set", "foo", "bar"];
     let get_args = ["get", "foo"];
 
     // Create a new empty database
-    Command::cargo_bin(PRG)?.args(init_args).assert().success();
+    kvstore(&dir)?.args(init_args).assert().success();
 
     // Add key/value pair
-    Command::cargo_bin(PRG)?.args(set_args).assert().success();
+    kvstore(&dir)?.args(set_args).assert().success();
 
     // Retrieve key/value pair
-    Command::cargo_bin(PRG)?
+    kvstore(&dir)?
         .args(get_args)
         .assert()
         .success()
@@ -35,3 +141,2116 @@ fn init_set_get() -> TestResult {
 
     Ok(())
 }
+
+#[test]
+fn set_appends_to_log() -> TestResult {
+    let dir = TempDir::new()?;
+
+    kvstore(&dir)?.args(["init"]).assert().success();
+    kvstore(&dir)?
+        .args(["set", "foo", "bar"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["set", "-f", "foo", "baz"])
+        .assert()
+        .success();
+
+    // The database file is left alone; the changes only go to the log.
+    assert_eq!(fs::read_to_string(dir.path().join("kv.db"))?, HEADER);
+    assert_eq!(
+        read_without_update_times(dir.path().join("kv.db.log"))?,
+        format!("{HEADER}set\tfoo\tbar\nset\tfoo\tbaz\tversion=2\n")
+    );
+
+    kvstore(&dir)?
+        .args(["get", "foo"])
+        .assert()
+        .success()
+        .stdout("foo : baz\n");
+
+    Ok(())
+}
+
+#[test]
+fn remove_is_replayed_from_log() -> TestResult {
+    let dir = TempDir::new()?;
+
+    kvstore(&dir)?
+        .args(["set", "foo", "bar"])
+        .assert()
+        .success();
+    kvstore(&dir)?.args(["remove", "foo"]).assert().success();
+
+    kvstore(&dir)?
+        .args(["get", "foo"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("No entry found for key 'foo'."));
+
+    Ok(())
+}
+
+#[test]
+fn partial_log_record_is_ignored() -> TestResult {
+    let dir = TempDir::new()?;
+    fs::write(dir.path().join("kv.db"), "foo\tbar\n")?;
+    fs::write(
+        dir.path().join("kv.db.log"),
+        format!("{HEADER}set\tfoo\tbaz\nset\tfo"),
+    )?;
+
+    kvstore(&dir)?
+        .args(["get", "foo"])
+        .assert()
+        .success()
+        .stdout("foo : baz\n");
+
+    // The partial record is discarded before new records are appended.
+    kvstore(&dir)?
+        .args(["set", "qux", "quux"])
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("kv.db.log"))?,
+        format!("{HEADER}set\tfoo\tbaz\nset\tqux\tquux\n")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn init_compacts_log() -> TestResult {
+    let dir = TempDir::new()?;
+
+    kvstore(&dir)?
+        .args(["set", "foo", "bar"])
+        .assert()
+        .success();
+    kvstore(&dir)?.args(["init"]).assert().success();
+
+    assert_eq!(fs::read_to_string(dir.path().join("kv.db"))?, HEADER);
+    assert_eq!(fs::read_to_string(dir.path().join("kv.db.log"))?, HEADER);
+
+    Ok(())
+}
+
+#[test]
+fn compaction_keeps_the_change_that_fills_the_log() -> TestResult {
+    let dir = TempDir::new()?;
+    let run = |args: &[&str]| -> TestResult {
+        kvstore(&dir)?.args(args).assert().success();
+        Ok(())
+    };
+
+    // The 1000th record in the log, a remove, triggers compaction.
+    for i in 1..=999 {
+        run(&["set", &format!("k{}", i), "v"])?;
+    }
+    run(&["remove", "k1"])?;
+    kvstore(&dir)?.args(["get", "k1"]).assert().failure();
+
+    // So does the 1000th record after that, a set.
+    for i in 2..=999 {
+        run(&["remove", &format!("k{}", i)])?;
+    }
+    run(&["set", "k1", "v"])?;
+    run(&["set", "k1000", "last"])?;
+    kvstore(&dir)?
+        .args(["get", "k1000"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("last"));
+    kvstore(&dir)?.args(["get", "k2"]).assert().failure();
+
+    Ok(())
+}
+
+#[test]
+fn interrupted_write_keeps_old_contents() -> TestResult {
+    let dir = TempDir::new()?;
+    fs::write(dir.path().join("kv.db"), "foo\tbar\n")?;
+
+    // A crash while writing leaves a partial temp file behind; the database
+    // file itself is untouched.
+    fs::write(dir.path().join("kv.db.tmp"), "foo\tgarb")?;
+    kvstore(&dir)?
+        .args(["get", "foo"])
+        .assert()
+        .success()
+        .stdout("foo : bar\n");
+
+    // Make the temp file impossible to write so the next rewrite fails
+    // part way through.
+    fs::remove_file(dir.path().join("kv.db.tmp"))?;
+    fs::create_dir(dir.path().join("kv.db.tmp"))?;
+    kvstore(&dir)?.args(["init"]).assert().failure();
+
+    assert_eq!(fs::read_to_string(dir.path().join("kv.db"))?, "foo\tbar\n");
+    kvstore(&dir)?
+        .args(["get", "foo"])
+        .assert()
+        .success()
+        .stdout("foo : bar\n");
+
+    // Once the write can go through, the database is replaced as a whole.
+    fs::remove_dir(dir.path().join("kv.db.tmp"))?;
+    kvstore(&dir)?.args(["init"]).assert().success();
+    assert!(!dir.path().join("kv.db.tmp").exists());
+    kvstore(&dir)?.args(["get", "foo"]).assert().failure();
+
+    Ok(())
+}
+
+#[test]
+fn tabs_and_newlines_round_trip() -> TestResult {
+    let dir = TempDir::new()?;
+    let key = "multi\tpart\\key";
+    let value = "line one\nline\ttwo\r\n";
+
+    kvstore(&dir)?.args(["set", key, value]).assert().success();
+    kvstore(&dir)?
+        .args(["get", key])
+        .assert()
+        .success()
+        .stdout(format!("{} : {}\n", key, value));
+
+    // The same escaping is used in the database file.
+    fs::write(
+        dir.path().join("kv.db"),
+        format!("{HEADER}multi\\tpart\\\\key\tline one\\nline\\ttwo\\r\\n\n"),
+    )?;
+    fs::remove_file(dir.path().join("kv.db.log"))?;
+    kvstore(&dir)?
+        .args(["get", key])
+        .assert()
+        .success()
+        .stdout(format!("{} : {}\n", key, value));
+
+    Ok(())
+}
+
+#[test]
+fn reads_version_1_files() -> TestResult {
+    let dir = TempDir::new()?;
+    fs::write(
+        dir.path().join("kv.db"),
+        "foo\tbar\nback\\slash\tC:\\temp\n",
+    )?;
+    fs::write(dir.path().join("kv.db.log"), "set\tfoo\tbaz\n")?;
+
+    kvstore(&dir)?
+        .args(["get", "foo"])
+        .assert()
+        .success()
+        .stdout("foo : baz\n");
+    kvstore(&dir)?
+        .args(["get", "back\\slash"])
+        .assert()
+        .success()
+        .stdout("back\\slash : C:\\temp\n");
+
+    // Reading leaves the files alone.
+    assert_eq!(
+        fs::read_to_string(dir.path().join("kv.db.log"))?,
+        "set\tfoo\tbaz\n"
+    );
+
+    // The version 1 log is compacted before writing so new records are never
+    // mixed into it.
+    kvstore(&dir)?
+        .args(["set", "qux", "quux"])
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("kv.db.log"))?,
+        format!("{HEADER}set\tqux\tquux\n")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn errors_have_distinct_exit_codes() -> TestResult {
+    let dir = TempDir::new()?;
+
+    kvstore(&dir)?
+        .args(["get", "foo"])
+        .assert()
+        .code(3)
+        .stderr("No entry found for key 'foo'.\n");
+
+    kvstore(&dir)?
+        .args(["set", "foo", "bar"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["set", "foo", "baz"])
+        .assert()
+        .code(4)
+        .stderr("foo already exists in database.\n");
+
+    Ok(())
+}
+
+#[test]
+fn corrupt_database_file_is_an_error() -> TestResult {
+    let dir = TempDir::new()?;
+    fs::write(
+        dir.path().join("kv.db"),
+        format!("{HEADER}foo\tbar\nno separator\n"),
+    )?;
+
+    kvstore(&dir)?
+        .args(["get", "foo"])
+        .assert()
+        .code(5)
+        .stderr(predicate::str::starts_with("kv.db:3: "));
+
+    // Nothing was changed on disk.
+    assert_eq!(
+        fs::read_to_string(dir.path().join("kv.db"))?,
+        format!("{HEADER}foo\tbar\nno separator\n")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn repair_quarantines_corrupt_lines() -> TestResult {
+    let dir = TempDir::new()?;
+    fs::write(
+        dir.path().join("kv.db"),
+        format!("{HEADER}foo\tbar\nno separator\nbad\\escape\tx\n"),
+    )?;
+    fs::write(
+        dir.path().join("kv.db.log"),
+        format!("{HEADER}set\tqux\tquux\nupdate\tfoo\tbaz\n"),
+    )?;
+
+    kvstore(&dir)?
+        .args(["--repair", "get", "foo"])
+        .assert()
+        .success()
+        .stdout("foo : bar\n")
+        .stderr("Quarantined 3 corrupt line(s) to kv.db.corrupt.\n");
+
+    assert_eq!(
+        fs::read_to_string(dir.path().join("kv.db.corrupt"))?,
+        "kv.db:3\tno separator\nkv.db:4\tbad\\escape\tx\nkv.db.log:3\tupdate\tfoo\tbaz\n"
+    );
+
+    // The remaining entries were rewritten without the corrupt lines.
+    kvstore(&dir)?
+        .args(["get", "qux"])
+        .assert()
+        .success()
+        .stdout("qux : quux\n");
+
+    Ok(())
+}
+
+#[test]
+fn db_flag_overrides_env() -> TestResult {
+    let dir = TempDir::new()?;
+
+    kvstore(&dir)?
+        .args(["--db", "other.db", "set", "foo", "bar"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["get", "foo", "--db", "other.db"])
+        .assert()
+        .success()
+        .stdout("foo : bar\n");
+
+    assert!(dir.path().join("other.db").exists());
+    assert!(!dir.path().join("kv.db").exists());
+
+    Ok(())
+}
+
+#[test]
+fn env_selects_database() -> TestResult {
+    let dir = TempDir::new()?;
+    let db = dir.path().join("shared.db");
+
+    Command::cargo_bin(PRG)?
+        .env("KVSTORE_DB", &db)
+        .args(["set", "foo", "bar"])
+        .assert()
+        .success();
+    Command::cargo_bin(PRG)?
+        .args(["--db", db.to_str().unwrap(), "get", "foo"])
+        .assert()
+        .success()
+        .stdout("foo : bar\n");
+
+    Ok(())
+}
+
+#[test]
+fn defaults_to_xdg_data_home() -> TestResult {
+    let dir = TempDir::new()?;
+
+    Command::cargo_bin(PRG)?
+        .env_remove("KVSTORE_DB")
+        .env("XDG_DATA_HOME", dir.path())
+        .args(["set", "foo", "bar"])
+        .assert()
+        .success();
+
+    assert!(dir.path().join("kvstore").join("kv.db").exists());
+
+    Ok(())
+}
+
+#[test]
+fn concurrent_sets_are_not_lost() -> TestResult {
+    let dir = TempDir::new()?;
+    let bin = assert_cmd::cargo::cargo_bin(PRG);
+
+    let children = (0..50)
+        .map(|i| {
+            process::Command::new(&bin)
+                .current_dir(dir.path())
+                .env("KVSTORE_DB", "kv.db")
+                .args(["set", &format!("key{}", i), &format!("value{}", i)])
+                .spawn()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    for mut child in children {
+        assert!(child.wait()?.success());
+    }
+
+    for i in 0..50 {
+        kvstore(&dir)?
+            .args(["get", &format!("key{}", i)])
+            .assert()
+            .success()
+            .stdout(format!("key{} : value{}\n", i, i));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn no_wait_fails_when_locked() -> TestResult {
+    let dir = TempDir::new()?;
+    kvstore(&dir)?
+        .args(["set", "foo", "bar"])
+        .assert()
+        .success();
+
+    // Another process reading the database doesn't block readers, but
+    // does block writers.
+    let lock = File::open(dir.path().join("kv.db.lock"))?;
+    lock.lock_shared()?;
+    kvstore(&dir)?
+        .args(["--no-wait", "get", "foo"])
+        .assert()
+        .success()
+        .stdout("foo : bar\n");
+    kvstore(&dir)?
+        .args(["--no-wait", "set", "-f", "foo", "baz"])
+        .assert()
+        .code(6)
+        .stderr("kv.db is locked by another process.\n");
+
+    // Another process writing to the database blocks everyone.
+    lock.unlock()?;
+    lock.lock()?;
+    kvstore(&dir)?
+        .args(["get", "foo", "--no-wait"])
+        .assert()
+        .code(6);
+
+    Ok(())
+}
+
+#[test]
+fn list_is_sorted_and_filtered() -> TestResult {
+    let dir = TempDir::new()?;
+    for (k, v) in [
+        ("user.bob", "2"),
+        ("app.name", "kv"),
+        ("user.alice", "1"),
+        ("user.carol", "3"),
+    ] {
+        kvstore(&dir)?.args(["set", k, v]).assert().success();
+    }
+
+    kvstore(&dir)?
+        .args(["list"])
+        .assert()
+        .success()
+        .stdout("app.name : kv\nuser.alice : 1\nuser.bob : 2\nuser.carol : 3\n");
+
+    kvstore(&dir)?
+        .args(["list", "--prefix", "user.", "--keys-only"])
+        .assert()
+        .success()
+        .stdout("user.alice\nuser.bob\nuser.carol\n");
+
+    kvstore(&dir)?
+        .args(["list", "--glob", "*.?o*"])
+        .assert()
+        .success()
+        .stdout("user.bob : 2\n");
+
+    kvstore(&dir)?
+        .args(["list", "--prefix", "user.", "--glob", "*a*"])
+        .assert()
+        .success()
+        .stdout("user.alice : 1\nuser.carol : 3\n");
+
+    kvstore(&dir)?
+        .args(["list", "--prefix", "nothing"])
+        .assert()
+        .success()
+        .stdout("");
+
+    Ok(())
+}
+
+#[test]
+fn scan_key_range() -> TestResult {
+    let dir = TempDir::new()?;
+    for k in ["d", "a", "c", "b", "e"] {
+        kvstore(&dir)?.args(["set", k, k]).assert().success();
+    }
+
+    kvstore(&dir)?
+        .args(["scan", "--from", "b", "--to", "d"])
+        .assert()
+        .success()
+        .stdout("b : b\nc : c\n");
+
+    kvstore(&dir)?
+        .args(["scan", "--from", "bb"])
+        .assert()
+        .success()
+        .stdout("c : c\nd : d\ne : e\n");
+
+    kvstore(&dir)?
+        .args(["scan", "--to", "b"])
+        .assert()
+        .success()
+        .stdout("a : a\n");
+
+    kvstore(&dir)?
+        .args(["scan", "--from", "d", "--to", "b"])
+        .assert()
+        .failure()
+        .stderr("--from must not come after --to.\n");
+
+    Ok(())
+}
+
+#[test]
+fn database_file_is_sorted() -> TestResult {
+    let dir = TempDir::new()?;
+    fs::write(
+        dir.path().join("kv.db.log"),
+        format!("{HEADER}set\tb\tb\nset\tc\tc\nset\ta\ta\ncorrupt\n"),
+    )?;
+
+    // Any rewrite of the database file writes the keys in order.
+    kvstore(&dir)?
+        .args(["--repair", "get", "a"])
+        .assert()
+        .success();
+
+    assert_eq!(
+        fs::read_to_string(dir.path().join("kv.db"))?,
+        format!("{HEADER}a\ta\nb\tb\nc\tc\n")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn output_formats() -> TestResult {
+    let dir = TempDir::new()?;
+    kvstore(&dir)?
+        .args(["set", "url", "http://host : 80"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["set", "tab", "a\tb"])
+        .assert()
+        .success();
+
+    kvstore(&dir)?
+        .args(["--output", "raw", "get", "url"])
+        .assert()
+        .success()
+        .stdout("http://host : 80");
+    kvstore(&dir)?
+        .args(["get", "url", "-o", "json"])
+        .assert()
+        .success()
+        .stdout("{\"key\":\"url\",\"value\":\"http://host : 80\",\"version\":1}\n");
+    kvstore(&dir)?
+        .args(["get", "tab", "-o", "tsv"])
+        .assert()
+        .success()
+        .stdout("tab\ta\\tb\n");
+
+    kvstore(&dir)?
+        .args(["list", "-o", "json"])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "[{\"key\":\"tab\",\"value\":\"a\\tb\"},",
+            "{\"key\":\"url\",\"value\":\"http://host : 80\"}]\n"
+        ));
+    kvstore(&dir)?
+        .args(["list", "--keys-only", "-o", "json"])
+        .assert()
+        .success()
+        .stdout("[\"tab\",\"url\"]\n");
+    kvstore(&dir)?
+        .args(["scan", "--from", "u", "-o", "raw"])
+        .assert()
+        .success()
+        .stdout("http://host : 80\n");
+
+    kvstore(&dir)?
+        .args(["remove", "url", "-o", "json"])
+        .assert()
+        .success()
+        .stdout("{\"key\":\"url\",\"value\":\"http://host : 80\"}\n");
+
+    kvstore(&dir)?
+        .args(["get", "tab", "-o", "yaml"])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+fn expired_keys_are_missing() -> TestResult {
+    let dir = TempDir::new()?;
+    kvstore(&dir)?
+        .args(["set", "--ttl", "1s", "token", "abc"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["set", "--ttl", "1h", "session", "xyz"])
+        .assert()
+        .success();
+
+    kvstore(&dir)?
+        .args(["get", "token"])
+        .assert()
+        .success()
+        .stdout("token : abc\n");
+    assert!(fs::read_to_string(dir.path().join("kv.db.log"))?.contains("\tabc\texpires="));
+
+    thread::sleep(Duration::from_millis(1100));
+
+    kvstore(&dir)?.args(["get", "token"]).assert().code(3);
+    kvstore(&dir)?
+        .args(["list", "--keys-only"])
+        .assert()
+        .success()
+        .stdout("session\n");
+
+    // An expired key can be set again without --force.
+    kvstore(&dir)?
+        .args(["set", "token", "def"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["get", "token"])
+        .assert()
+        .success()
+        .stdout("token : def\n");
+
+    Ok(())
+}
+
+#[test]
+fn purge_removes_expired_keys() -> TestResult {
+    let dir = TempDir::new()?;
+    kvstore(&dir)?
+        .args(["set", "--ttl", "1", "a", "1"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["set", "--ttl", "1s", "b", "2"])
+        .assert()
+        .success();
+    kvstore(&dir)?.args(["set", "c", "3"]).assert().success();
+
+    thread::sleep(Duration::from_millis(1100));
+
+    kvstore(&dir)?
+        .args(["purge", "-o", "json"])
+        .assert()
+        .success()
+        .stdout("[\"a\",\"b\"]\n");
+    kvstore(&dir)?
+        .args(["purge"])
+        .assert()
+        .success()
+        .stdout("Purged 0 expired key(s).\n");
+    assert!(fs::read_to_string(dir.path().join("kv.db.log"))?.ends_with("remove\ta\nremove\tb\n"));
+
+    Ok(())
+}
+
+#[test]
+fn invalid_ttl_is_rejected() -> TestResult {
+    let dir = TempDir::new()?;
+    for ttl in ["10x", "0", "m", "-5s"] {
+        kvstore(&dir)?
+            .args(["set", "--ttl", ttl, "foo", "bar"])
+            .assert()
+            .failure();
+    }
+    kvstore(&dir)?.args(["get", "foo"]).assert().code(3);
+
+    Ok(())
+}
+
+#[test]
+fn huge_ttl_does_not_wrap_around() -> TestResult {
+    for backend in ["tsv", "json", "sqlite"] {
+        let dir = TempDir::new()?;
+        // Too many milliseconds for a u64, which used to wrap around to a
+        // fraction of a second.
+        kvstore(&dir)?
+            .args(["--backend", backend])
+            .args(["set", "--ttl", "18446744073709552s", "foo", "bar"])
+            .assert()
+            .success();
+
+        thread::sleep(Duration::from_millis(500));
+
+        kvstore(&dir)?
+            .args(["--backend", backend, "get", "foo"])
+            .assert()
+            .success()
+            .stdout("foo : bar\n");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn import_formats() -> TestResult {
+    let dir = TempDir::new()?;
+
+    kvstore(&dir)?
+        .args(["import"])
+        .write_stdin("a\t1\nb\tline\\none\n")
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["import", "--format", "json"])
+        .write_stdin(r#"{"c": "3", "d": 4, "e": true}"#)
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["import", "-F", "jsonl"])
+        .write_stdin("{\"key\":\"f\",\"value\":\"6\"}\n\n{\"key\":\"g\",\"value\":\"7\"}\n")
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["import", "-F", "env"])
+        .write_stdin("# settings\nH=8\nexport I=\"quoted \\\"9\\\"\"\nJ='$literal'\n")
+        .assert()
+        .success();
+
+    kvstore(&dir)?
+        .args(["list", "-o", "tsv"])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "H\t8\nI\tquoted \"9\"\nJ\t$literal\n",
+            "a\t1\nb\tline\\none\nc\t3\nd\t4\ne\ttrue\nf\t6\ng\t7\n"
+        ));
+
+    kvstore(&dir)?
+        .args(["import", "-F", "jsonl"])
+        .write_stdin("{\"key\":\"x\"}\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::starts_with("line 1: "));
+
+    Ok(())
+}
+
+#[test]
+fn import_merge_and_replace() -> TestResult {
+    let dir = TempDir::new()?;
+    kvstore(&dir)?.args(["set", "a", "old"]).assert().success();
+    kvstore(&dir)?.args(["set", "c", "kept"]).assert().success();
+
+    // Importing fails without importing anything when a key already exists.
+    kvstore(&dir)?
+        .args(["import", "--merge"])
+        .write_stdin("b\tnew\na\tnew\n")
+        .assert()
+        .code(4)
+        .stderr("a already exists in database.\n");
+    kvstore(&dir)?.args(["get", "b"]).assert().code(3);
+
+    kvstore(&dir)?
+        .args(["import", "--replace"])
+        .write_stdin("b\tnew\na\tnew\n")
+        .assert()
+        .success();
+    // The import is written as a single transaction.
+    let log = fs::read_to_string(dir.path().join("kv.db.log"))?;
+    assert!(log.contains("\nbegin\nset\tb\tnew\nset\ta\tnew\tversion=2\t"));
+    assert!(log.ends_with("\ncommit\n"));
+    kvstore(&dir)?
+        .args(["list"])
+        .assert()
+        .success()
+        .stdout("a : new\nb : new\nc : kept\n");
+
+    Ok(())
+}
+
+#[test]
+fn import_rejects_duplicate_keys() -> TestResult {
+    let dir = TempDir::new()?;
+    kvstore(&dir)?
+        .args(["import", "--replace"])
+        .write_stdin("a\t1\nb\t2\na\t3\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("line 3: duplicate key 'a'"));
+    kvstore(&dir)?
+        .args(["import", "-F", "json"])
+        .write_stdin("{\"a\": \"1\", \"a\": \"2\"}")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("duplicate key 'a'"));
+    kvstore(&dir)?.args(["list"]).assert().success().stdout("");
+
+    Ok(())
+}
+
+#[test]
+fn export_formats() -> TestResult {
+    let dir = TempDir::new()?;
+    kvstore(&dir)?
+        .args(["import"])
+        .write_stdin("NAME\tkv store\nPATH\tC:\\\\bin\\t$HOME\n")
+        .assert()
+        .success();
+
+    kvstore(&dir)?
+        .args(["export"])
+        .assert()
+        .success()
+        .stdout("NAME\tkv store\nPATH\tC:\\\\bin\\t$HOME\n");
+    kvstore(&dir)?
+        .args(["export", "-F", "json"])
+        .assert()
+        .success()
+        .stdout("{\n  \"NAME\": \"kv store\",\n  \"PATH\": \"C:\\\\bin\\t$HOME\"\n}\n");
+    kvstore(&dir)?
+        .args(["export", "-F", "jsonl"])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "{\"key\":\"NAME\",\"value\":\"kv store\"}\n",
+            "{\"key\":\"PATH\",\"value\":\"C:\\\\bin\\t$HOME\"}\n"
+        ));
+    let env = "NAME=\"kv store\"\nPATH=\"C:\\\\bin\\t\\$HOME\"\n";
+    kvstore(&dir)?
+        .args(["export", "-F", "env"])
+        .assert()
+        .success()
+        .stdout(env);
+
+    // Exported .env files read back unchanged.
+    let other = TempDir::new()?;
+    kvstore(&other)?
+        .args(["import", "-F", "env"])
+        .write_stdin(env)
+        .assert()
+        .success();
+    kvstore(&other)?
+        .args(["export"])
+        .assert()
+        .success()
+        .stdout("NAME\tkv store\nPATH\tC:\\\\bin\\t$HOME\n");
+
+    kvstore(&dir)?
+        .args(["set", "not-a-name", "x"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["export", "-F", "env"])
+        .assert()
+        .failure()
+        .stdout("");
+
+    Ok(())
+}
+
+#[test]
+fn batch_applies_all_operations() -> TestResult {
+    let dir = TempDir::new()?;
+    kvstore(&dir)?.args(["set", "a", "1"]).assert().success();
+    kvstore(&dir)?.args(["set", "b", "2"]).assert().success();
+
+    let script = concat!(
+        "# Move a to c.\n",
+        "check a 1\n",
+        "check --absent c\n",
+        "\n",
+        "set c 1\n",
+        "remove a\n",
+        "set -f b \"two\\tand a half\"\n",
+        "set d 'hello world'  # trailing comment\n",
+    );
+    kvstore(&dir)?
+        .args(["batch"])
+        .write_stdin(script)
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["export"])
+        .assert()
+        .success()
+        .stdout("b\ttwo\\tand a half\nc\t1\nd\thello world\n");
+
+    Ok(())
+}
+
+#[test]
+fn failed_batch_changes_nothing() -> TestResult {
+    let dir = TempDir::new()?;
+    kvstore(&dir)?.args(["set", "a", "1"]).assert().success();
+
+    kvstore(&dir)?
+        .args(["batch"])
+        .write_stdin("set b 2\ncheck a 2\n")
+        .assert()
+        .failure()
+        .code(7)
+        .stderr(predicate::str::contains(
+            "line 2: 'a' doesn't hold the expected value.",
+        ));
+    kvstore(&dir)?
+        .args(["batch"])
+        .write_stdin("set b 2\nremove c\n")
+        .assert()
+        .failure()
+        .code(3);
+    kvstore(&dir)?
+        .args(["batch"])
+        .write_stdin("set b 2\nset a 2\n")
+        .assert()
+        .failure()
+        .code(4);
+    kvstore(&dir)?
+        .args(["batch"])
+        .write_stdin("set b 2\nrename a c\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("line 2: invalid operation"));
+
+    kvstore(&dir)?
+        .args(["export"])
+        .assert()
+        .success()
+        .stdout("a\t1\n");
+
+    Ok(())
+}
+
+#[test]
+fn uncommitted_transactions_are_ignored() -> TestResult {
+    let dir = TempDir::new()?;
+    fs::write(dir.path().join("kv.db"), format!("{}a\t1\n", HEADER))?;
+
+    // A writer that stopped part way through a transaction leaves no commit.
+    fs::write(
+        dir.path().join("kv.db.log"),
+        format!(
+            "{}begin\nset\tb\t2\nremove\ta\ncommit\nbegin\nset\tc\t3\n",
+            HEADER
+        ),
+    )?;
+    kvstore(&dir)?
+        .args(["export"])
+        .assert()
+        .success()
+        .stdout("b\t2\n");
+
+    // The next writer drops the unfinished transaction before appending.
+    kvstore(&dir)?.args(["set", "d", "4"]).assert().success();
+    kvstore(&dir)?
+        .args(["export"])
+        .assert()
+        .success()
+        .stdout("b\t2\nd\t4\n");
+    let log = fs::read_to_string(dir.path().join("kv.db.log"))?;
+    assert!(!log.contains("set\tc\t3"));
+
+    Ok(())
+}
+
+#[test]
+fn set_with_preconditions() -> TestResult {
+    let dir = TempDir::new()?;
+    kvstore(&dir)?
+        .args(["set", "--if-absent", "job", "queued"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["set", "--if-absent", "job", "queued"])
+        .assert()
+        .failure()
+        .code(7)
+        .stderr("Precondition failed: 'job' exists.\n");
+
+    kvstore(&dir)?
+        .args(["set", "--if-value", "queued", "job", "running"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["set", "--if-value", "queued", "job", "running"])
+        .assert()
+        .failure()
+        .code(7)
+        .stderr("Precondition failed: 'job' doesn't hold the expected value.\n");
+    kvstore(&dir)?
+        .args(["set", "--if-value", "x", "other", "y"])
+        .assert()
+        .failure()
+        .code(7)
+        .stderr("Precondition failed: 'other' doesn't exist.\n");
+
+    kvstore(&dir)?
+        .args(["get", "job", "-o", "json"])
+        .assert()
+        .success()
+        .stdout("{\"key\":\"job\",\"value\":\"running\",\"version\":2}\n");
+    kvstore(&dir)?
+        .args(["set", "--if-version", "1", "job", "done"])
+        .assert()
+        .failure()
+        .code(7)
+        .stderr("Precondition failed: 'job' is at version 2, not 1.\n");
+    kvstore(&dir)?
+        .args(["set", "--if-version", "2", "job", "done"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["get", "job"])
+        .assert()
+        .success()
+        .stdout("job : done\n");
+
+    // Preconditions replace --force rather than combining with it.
+    kvstore(&dir)?
+        .args(["set", "--if-absent", "--force", "job", "x"])
+        .assert()
+        .failure()
+        .code(2);
+    kvstore(&dir)?
+        .args(["set", "--if-absent", "--if-version", "1", "job", "x"])
+        .assert()
+        .failure()
+        .code(2);
+
+    Ok(())
+}
+
+#[test]
+fn versions_are_persisted() -> TestResult {
+    let dir = TempDir::new()?;
+    kvstore(&dir)?.args(["set", "a", "1"]).assert().success();
+    kvstore(&dir)?
+        .args(["set", "-f", "a", "2"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["batch"])
+        .write_stdin("check --version 2 a\nset -f a 3\n")
+        .assert()
+        .success();
+    assert_eq!(
+        read_without_update_times(dir.path().join("kv.db.log"))?,
+        format!(
+            "{}set\ta\t1\nset\ta\t2\tversion=2\nbegin\nset\ta\t3\tversion=3\ncommit\n",
+            HEADER
+        )
+    );
+
+    // Versions survive compaction, and start over once a key is removed.
+    kvstore(&dir)?.args(["init"]).assert().success();
+    fs::write(
+        dir.path().join("kv.db"),
+        format!("{}a\t3\tversion=3\nb\t1\n", HEADER),
+    )?;
+    kvstore(&dir)?
+        .args(["--repair", "set", "--if-version", "3", "a", "4"])
+        .assert()
+        .success();
+    kvstore(&dir)?.args(["remove", "b"]).assert().success();
+    kvstore(&dir)?
+        .args(["set", "b", "again"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["get", "a", "-o", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"version\":4"));
+    kvstore(&dir)?
+        .args(["get", "b", "-o", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"version\":1"));
+
+    Ok(())
+}
+
+#[test]
+fn history_and_rollback() -> TestResult {
+    let dir = TempDir::new()?;
+    kvstore(&dir)?.args(["set", "a", "one"]).assert().success();
+    kvstore(&dir)?
+        .args(["set", "-f", "a", "two"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["set", "-f", "a", "three"])
+        .assert()
+        .success();
+
+    kvstore(&dir)?
+        .args(["history", "a"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(
+            "^3 : three \\(current\\)\n2 : two \\(replaced .* UTC\\)\n1 : one \\(replaced .* UTC\\)\n$",
+        )?);
+    kvstore(&dir)?
+        .args(["history", "a", "-o", "raw"])
+        .assert()
+        .success()
+        .stdout("three\ntwo\none\n");
+
+    // Rolling back sets the old value as a new version.
+    kvstore(&dir)?.args(["rollback", "a"]).assert().success();
+    kvstore(&dir)?
+        .args(["get", "a", "-o", "json"])
+        .assert()
+        .success()
+        .stdout("{\"key\":\"a\",\"value\":\"two\",\"version\":4}\n");
+    kvstore(&dir)?
+        .args(["rollback", "a", "--to", "1"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["history", "a", "-o", "raw"])
+        .assert()
+        .success()
+        .stdout("one\ntwo\nthree\ntwo\none\n");
+    kvstore(&dir)?
+        .args(["rollback", "a", "--to", "5"])
+        .assert()
+        .failure()
+        .code(8)
+        .stderr("Version 5 of 'a' isn't in its history.\n");
+
+    // Removing a key discards its history.
+    kvstore(&dir)?.args(["remove", "a"]).assert().success();
+    kvstore(&dir)?.args(["set", "a", "new"]).assert().success();
+    kvstore(&dir)?
+        .args(["rollback", "a"])
+        .assert()
+        .failure()
+        .code(8)
+        .stderr("'a' has no earlier versions.\n");
+    kvstore(&dir)?
+        .args(["history", "b"])
+        .assert()
+        .failure()
+        .code(3);
+
+    Ok(())
+}
+
+#[test]
+fn history_is_bounded() -> TestResult {
+    let dir = TempDir::new()?;
+    let script: String = (1..=15).map(|i| format!("set -f a {}\n", i)).collect();
+    for line in script.lines() {
+        kvstore(&dir)?
+            .args(["batch"])
+            .write_stdin(line)
+            .assert()
+            .success();
+    }
+
+    kvstore(&dir)?
+        .args(["history", "a", "-o", "raw"])
+        .assert()
+        .success()
+        .stdout("15\n14\n13\n12\n11\n10\n9\n8\n7\n6\n5\n");
+
+    Ok(())
+}
+
+#[test]
+fn history_is_kept_in_database_file() -> TestResult {
+    let dir = TempDir::new()?;
+    fs::write(
+        dir.path().join("kv.db"),
+        format!(
+            "{}a\tthree\tversion=3\tupdated=951782400000\tprev=1:951696000000:one\tprev=2:951782400000:t\\two\n",
+            HEADER
+        ),
+    )?;
+    kvstore(&dir)?
+        .args(["history", "a"])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "3 : three (current)\n",
+            "2 : t\two (replaced 2000-02-29 00:00:00 UTC)\n",
+            "1 : one (replaced 2000-02-28 00:00:00 UTC)\n"
+        ));
+    kvstore(&dir)?
+        .args(["history", "a", "-o", "tsv"])
+        .assert()
+        .success()
+        .stdout("3\tthree\t\n2\tt\\two\t951782400000\n1\tone\t951696000000\n");
+
+    // History is written back out when the log is compacted, which happens
+    // as soon as a writer opens a database with a version 1 log.
+    fs::write(dir.path().join("kv.db.log"), "set\tb\tx\n")?;
+    kvstore(&dir)?
+        .args(["set", "-f", "a", "four"])
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("kv.db"))?,
+        format!(
+            "{}a\tthree\tversion=3\tupdated=951782400000\tprev=1:951696000000:one\tprev=2:951782400000:t\\two\nb\tx\n",
+            HEADER
+        )
+    );
+    kvstore(&dir)?
+        .args(["history", "a", "-o", "raw"])
+        .assert()
+        .success()
+        .stdout("four\nthree\nt\two\none\n");
+
+    Ok(())
+}
+
+#[test]
+fn namespaces_keep_keys_apart() -> TestResult {
+    let dir = TempDir::new()?;
+    kvstore(&dir)?
+        .args(["set", "url", "local"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["--ns", "prod", "set", "url", "remote"])
+        .assert()
+        .failure()
+        .code(9)
+        .stderr("No namespace named 'prod'.\n");
+
+    kvstore(&dir)?
+        .args(["ns", "create", "prod"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["ns", "create", "prod"])
+        .assert()
+        .failure()
+        .code(4);
+    kvstore(&dir)?
+        .args(["--ns", "prod", "set", "url", "remote"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["set", "url", "remote"])
+        .env("KVSTORE_NS", "prod")
+        .assert()
+        .failure()
+        .code(4);
+
+    kvstore(&dir)?
+        .args(["get", "url"])
+        .assert()
+        .success()
+        .stdout("url : local\n");
+    kvstore(&dir)?
+        .args(["list", "--ns", "prod"])
+        .assert()
+        .success()
+        .stdout("url : remote\n");
+    kvstore(&dir)?
+        .args(["ns", "list"])
+        .assert()
+        .success()
+        .stdout("default\nprod\n");
+
+    kvstore(&dir)?
+        .args(["ns", "drop", "prod"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["ns", "list", "-o", "json"])
+        .assert()
+        .success()
+        .stdout("[\"default\"]\n");
+    kvstore(&dir)?
+        .args(["ns", "drop", "default"])
+        .assert()
+        .failure();
+    kvstore(&dir)?
+        .args(["get", "url"])
+        .assert()
+        .success()
+        .stdout("url : local\n");
+
+    Ok(())
+}
+
+#[test]
+fn namespaces_are_stored_in_sections() -> TestResult {
+    let dir = TempDir::new()?;
+    fs::write(
+        dir.path().join("kv.db"),
+        format!("{}a\t1\nns=prod\na\t2\nns=empty\n", HEADER),
+    )?;
+    kvstore(&dir)?
+        .args(["ns", "list"])
+        .assert()
+        .success()
+        .stdout("default\nempty\nprod\n");
+    kvstore(&dir)?
+        .args(["--ns", "prod", "get", "a"])
+        .assert()
+        .success()
+        .stdout("a : 2\n");
+
+    // Records for other namespaces say which one they belong to.
+    kvstore(&dir)?
+        .args(["ns", "create", "alpha"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["--ns", "alpha", "set", "b\tc", "3"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["--ns", "prod", "remove", "a"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["ns", "drop", "empty"])
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("kv.db.log"))?,
+        format!(
+            "{}create\talpha\nset\tb\\tc\t3\tns=alpha\nremove\ta\tns=prod\ndrop\tempty\n",
+            HEADER
+        )
+    );
+
+    // The default namespace is written first, even though other names sort
+    // before it. A version 1 log is compacted as soon as a writer opens it.
+    fs::write(
+        dir.path().join("kv.db"),
+        format!("{}ns=alpha\nb\t3\n", HEADER),
+    )?;
+    fs::write(dir.path().join("kv.db.log"), "set\td\t4\n")?;
+    kvstore(&dir)?.args(["set", "e", "5"]).assert().success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("kv.db"))?,
+        format!("{}d\t4\nns=alpha\nb\t3\n", HEADER)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn encrypted_database() -> TestResult {
+    let dir = TempDir::new()?;
+    kvstore(&dir)?
+        .args(["set", "password", "hunter2"])
+        .env("KVSTORE_PASSPHRASE", "correct horse")
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["set", "-f", "password", "hunter3"])
+        .env("KVSTORE_PASSPHRASE", "correct horse")
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["get", "password"])
+        .env("KVSTORE_PASSPHRASE", "correct horse")
+        .assert()
+        .success()
+        .stdout("password : hunter3\n");
+
+    // Neither file gives anything away.
+    for file in ["kv.db", "kv.db.log"] {
+        let contents = fs::read_to_string(dir.path().join(file))?;
+        assert!(contents.starts_with("#kvstore encrypted\targon2id\t"));
+        assert!(!contents.contains("password") && !contents.contains("hunter"));
+    }
+
+    kvstore(&dir)?
+        .args(["get", "password"])
+        .assert()
+        .failure()
+        .code(10)
+        .stderr("kv.db is encrypted; set KVSTORE_PASSPHRASE or use --key-file.\n");
+    kvstore(&dir)?
+        .args(["get", "password"])
+        .env("KVSTORE_PASSPHRASE", "wrong horse")
+        .assert()
+        .failure()
+        .code(11)
+        .stderr(predicate::str::starts_with("Can't decrypt kv.db: "));
+
+    // One trailing newline in a key file isn't part of the passphrase.
+    for contents in ["correct horse", "correct horse\n", "correct horse\r\n"] {
+        fs::write(dir.path().join("key"), contents)?;
+        kvstore(&dir)?
+            .args(["--key-file", "key", "list"])
+            .assert()
+            .success()
+            .stdout("password : hunter3\n");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn passphrase_encrypts_existing_database() -> TestResult {
+    let dir = TempDir::new()?;
+    kvstore(&dir)?.args(["set", "a", "1"]).assert().success();
+
+    // Readers leave the database as it is.
+    kvstore(&dir)?
+        .args(["get", "a"])
+        .env("KVSTORE_PASSPHRASE", "secret")
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(dir.path().join("kv.db.log"))?,
+        format!("{}set\ta\t1\n", HEADER)
+    );
+
+    kvstore(&dir)?
+        .args(["set", "b", "2"])
+        .env("KVSTORE_PASSPHRASE", "secret")
+        .assert()
+        .success();
+    let db = fs::read_to_string(dir.path().join("kv.db"))?;
+    assert!(db.starts_with("#kvstore encrypted\t"));
+    kvstore(&dir)?
+        .args(["list"])
+        .env("KVSTORE_PASSPHRASE", "secret")
+        .assert()
+        .success()
+        .stdout("a : 1\nb : 2\n");
+
+    Ok(())
+}
+
+#[test]
+fn tampered_log_record_is_detected() -> TestResult {
+    let dir = TempDir::new()?;
+    kvstore(&dir)?
+        .args(["set", "a", "1"])
+        .env("KVSTORE_PASSPHRASE", "secret")
+        .assert()
+        .success();
+
+    // Flip a character in the sealed record.
+    let log = fs::read_to_string(dir.path().join("kv.db.log"))?;
+    let (header, record) = log.split_once('\n').unwrap();
+    let flipped = if record.starts_with('A') { "B" } else { "A" };
+    fs::write(
+        dir.path().join("kv.db.log"),
+        format!("{}\n{}{}", header, flipped, &record[1..]),
+    )?;
+
+    kvstore(&dir)?
+        .args(["get", "a"])
+        .env("KVSTORE_PASSPHRASE", "secret")
+        .assert()
+        .failure()
+        .code(5)
+        .stderr(predicate::str::contains(
+            "kv.db.log:2: can't decrypt record",
+        ));
+
+    Ok(())
+}
+
+#[test]
+fn binary_values() -> TestResult {
+    let dir = TempDir::new()?;
+    let blob: Vec<u8> = vec![0, 1, 0xff, b'\t', b'\n', 0xc3, b'a', b'\\'];
+    fs::write(dir.path().join("blob.bin"), &blob)?;
+    kvstore(&dir)?
+        .args(["set", "blob", "--from-file", "blob.bin"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["set", "piped", "--from-file", "-"])
+        .write_stdin(blob.clone())
+        .assert()
+        .success();
+
+    for key in ["blob", "piped"] {
+        kvstore(&dir)?
+            .args(["get", "--raw", key])
+            .assert()
+            .success()
+            .stdout(blob.clone());
+    }
+    kvstore(&dir)?
+        .args(["--output", "tsv", "get", "blob"])
+        .assert()
+        .success()
+        .stdout("blob\t\0\x01\\xff\\t\\n\\xc3a\\\\\n");
+
+    // Values survive a round trip through the database file and a tsv export.
+    let output = kvstore(&dir)?.args(["export"]).output()?;
+    assert!(output.status.success());
+    kvstore(&dir)?.args(["init"]).assert().success();
+    kvstore(&dir)?
+        .args(["import"])
+        .write_stdin(output.stdout)
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["get", "--raw", "piped"])
+        .assert()
+        .success()
+        .stdout(blob);
+
+    kvstore(&dir)?
+        .args(["export", "--format", "json"])
+        .assert()
+        .failure()
+        .stderr("The value of 'blob' is binary and can only be exported as tsv.\n");
+    kvstore(&dir)?
+        .args(["set", "blob", "value", "--from-file", "blob.bin"])
+        .assert()
+        .failure();
+    Ok(())
+}
+
+#[test]
+fn reads_leave_files_untouched() -> TestResult {
+    for backend in ["tsv", "json", "sqlite"] {
+        let dir = TempDir::new()?;
+        kvstore(&dir)?
+            .args(["--backend", backend, "get", "foo"])
+            .assert()
+            .code(3);
+        kvstore(&dir)?
+            .args(["--backend", backend, "list"])
+            .assert()
+            .success()
+            .stdout("");
+        assert_eq!(fs::read_dir(dir.path())?.count(), 0, "{}", backend);
+    }
+
+    let dir = TempDir::new()?;
+    kvstore(&dir)?
+        .args(["set", "foo", "bar"])
+        .assert()
+        .success();
+    let modified = |file| fs::metadata(dir.path().join(file))?.modified();
+    let before = (modified("kv.db")?, modified("kv.db.log")?);
+    thread::sleep(Duration::from_millis(20));
+    kvstore(&dir)?
+        .args(["get", "foo"])
+        .assert()
+        .success()
+        .stdout("foo : bar\n");
+    kvstore(&dir)?.args(["list"]).assert().success();
+    assert_eq!((modified("kv.db")?, modified("kv.db.log")?), before);
+
+    Ok(())
+}
+
+#[test]
+fn serve_and_remote() -> TestResult {
+    let dir = TempDir::new()?;
+    let server = Server::start(&dir, &["--listen", "tcp:127.0.0.1:0"])?;
+    let remote = |args: &[&str]| -> Result<Command, Box<dyn std::error::Error>> {
+        let mut cmd = kvstore(&dir)?;
+        cmd.args(["--remote", &server.address]).args(args);
+        Ok(cmd)
+    };
+
+    remote(&["set", "foo", "bar"])?.assert().success();
+    remote(&["set", "foo", "baz"])?
+        .assert()
+        .code(4)
+        .stderr("foo already exists in database.\n");
+    remote(&["set", "-f", "foo", "line\tone"])?
+        .assert()
+        .success();
+    remote(&["set", "app.name", "kv"])?.assert().success();
+    remote(&["get", "foo"])?
+        .assert()
+        .success()
+        .stdout("foo : line\tone\n");
+    remote(&["get", "-o", "json", "foo"])?
+        .assert()
+        .success()
+        .stdout("{\"key\":\"foo\",\"value\":\"line\\tone\",\"version\":2}\n");
+    remote(&["list", "--keys-only"])?
+        .assert()
+        .success()
+        .stdout("app.name\nfoo\n");
+    remote(&["list", "--prefix", "app."])?
+        .assert()
+        .success()
+        .stdout("app.name : kv\n");
+    remote(&["remove", "foo"])?
+        .assert()
+        .success()
+        .stdout("(foo : line\tone) removed from database.\n");
+    remote(&["get", "foo"])?
+        .assert()
+        .code(3)
+        .stderr("No entry found for key 'foo'.\n");
+    remote(&["--ns", "missing", "get", "app.name"])?
+        .assert()
+        .code(9);
+    remote(&["purge"])?.assert().failure();
+
+    // The server writes changes to the database files as it goes.
+    assert_eq!(
+        read_without_update_times(dir.path().join("kv.db.log"))?,
+        concat!(
+            "#kvstore v2\n",
+            "set\tfoo\tbar\n",
+            "set\tfoo\tline\\tone\tversion=2\n",
+            "set\tapp.name\tkv\n",
+            "remove\tfoo\n"
+        )
+    );
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn serve_line_protocol_on_unix_socket() -> TestResult {
+    use std::os::unix::net::UnixStream;
+
+    let dir = TempDir::new()?;
+    let socket = dir.path().join("kv.sock");
+    let server = Server::start(&dir, &["--listen", &format!("unix:{}", socket.display())])?;
+    assert_eq!(server.address, format!("unix:{}", socket.display()));
+
+    let mut stream = UnixStream::connect(&socket)?;
+    stream.write_all(b"SET\ta\t\\xff\\n\nset\tb\t2\tttl=60\nGET\ta\nLIST\nDEL\tc\nBOGUS\n")?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let mut responses = String::new();
+    stream.read_to_string(&mut responses)?;
+    assert_eq!(
+        responses,
+        concat!(
+            "OK\n",
+            "OK\n",
+            "OK\t\\xff\\n\t1\n",
+            "OK\t2\na\t\\xff\\n\nb\t2\n",
+            "ERR\t3\tNo entry found for key 'c'.\n",
+            "ERR\t1\tinvalid request 'BOGUS'\n",
+        )
+    );
+
+    Ok(())
+}
+
+// Encode a command the way Redis clients send it, as an array of bulk strings.
+fn resp_command(args: &[&str]) -> String {
+    let mut command = format!("*{}\r\n", args.len());
+    for arg in args {
+        command.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+    }
+    command
+}
+
+#[test]
+fn serve_resp() -> TestResult {
+    let dir = TempDir::new()?;
+    let server = Server::start(&dir, &["--listen", "tcp:127.0.0.1:0", "--protocol", "resp"])?;
+
+    let commands = [
+        resp_command(&["PING"]),
+        resp_command(&["SET", "foo", "bar"]),
+        resp_command(&["SET", "foo", "baz", "NX"]),
+        resp_command(&["SET", "missing", "x", "XX"]),
+        resp_command(&["set", "foo", "baz", "xx", "ex", "100"]),
+        resp_command(&["GET", "foo"]),
+        resp_command(&["GET", "missing"]),
+        resp_command(&["TTL", "foo"]),
+        resp_command(&["INCR", "counter"]),
+        resp_command(&["INCR", "counter"]),
+        resp_command(&["INCR", "foo"]),
+        resp_command(&["EXISTS", "foo", "counter", "missing"]),
+        resp_command(&["KEYS", "*o*"]),
+        resp_command(&["SCAN", "0", "COUNT", "1"]),
+        resp_command(&["SCAN", "1", "MATCH", "f*"]),
+        resp_command(&["EXPIRE", "counter", "50"]),
+        resp_command(&["EXPIRE", "missing", "50"]),
+        resp_command(&["DEL", "foo", "missing"]),
+        resp_command(&["GET"]),
+        resp_command(&["FLUSHALL"]),
+        // Commands can also be typed inline.
+        "exists counter\r\n".to_string(),
+    ];
+
+    let mut stream = std::net::TcpStream::connect(server.address.strip_prefix("tcp:").unwrap())?;
+    stream.write_all(commands.concat().as_bytes())?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let mut replies = String::new();
+    stream.read_to_string(&mut replies)?;
+    assert_eq!(
+        replies,
+        concat!(
+            "+PONG\r\n",
+            "+OK\r\n",
+            "$-1\r\n",
+            "$-1\r\n",
+            "+OK\r\n",
+            "$3\r\nbaz\r\n",
+            "$-1\r\n",
+            ":100\r\n",
+            ":1\r\n",
+            ":2\r\n",
+            "-ERR value is not an integer or out of range\r\n",
+            ":2\r\n",
+            "*2\r\n$7\r\ncounter\r\n$3\r\nfoo\r\n",
+            "*2\r\n$1\r\n1\r\n*1\r\n$7\r\ncounter\r\n",
+            "*2\r\n$1\r\n0\r\n*1\r\n$3\r\nfoo\r\n",
+            ":1\r\n",
+            ":0\r\n",
+            ":1\r\n",
+            "-ERR wrong number of arguments for 'get' command\r\n",
+            "-ERR unknown command 'flushall'\r\n",
+            ":1\r\n",
+        )
+    );
+
+    // The changes were written to the database.
+    drop(server);
+    kvstore(&dir)?
+        .args(["list"])
+        .assert()
+        .success()
+        .stdout("counter : 2\n");
+
+    Ok(())
+}
+
+#[test]
+fn serve_resp_del_removes_all_keys_or_none() -> TestResult {
+    let dir = TempDir::new()?;
+    kvstore(&dir)?.args(["set", "foo", "1"]).assert().success();
+    let server = Server::start(&dir, &["--listen", "tcp:127.0.0.1:0", "--protocol", "resp"])?;
+
+    let mut stream = std::net::TcpStream::connect(server.address.strip_prefix("tcp:").unwrap())?;
+    stream.write_all(b"*3\r\n$3\r\nDEL\r\n$3\r\nfoo\r\n$1\r\n\xff\r\n")?;
+    stream.write_all(resp_command(&["DEL", "foo", "foo", "missing"]).as_bytes())?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let mut replies = String::new();
+    stream.read_to_string(&mut replies)?;
+    assert_eq!(replies, "-ERR keys must be valid UTF-8\r\n:1\r\n");
+
+    Ok(())
+}
+
+#[test]
+fn serve_resp_rejects_oversized_bulk_strings() -> TestResult {
+    let dir = TempDir::new()?;
+    let server = Server::start(&dir, &["--listen", "tcp:127.0.0.1:0", "--protocol", "resp"])?;
+    let address = server.address.strip_prefix("tcp:").unwrap();
+
+    for length in ["99999999999999", "18446744073709551615"] {
+        let mut stream = std::net::TcpStream::connect(address)?;
+        stream.write_all(format!("*1\r\n${}\r\n", length).as_bytes())?;
+        let mut replies = String::new();
+        stream.read_to_string(&mut replies)?;
+        assert_eq!(replies, "-ERR Protocol error: invalid bulk length\r\n");
+    }
+
+    // Nor lines that never end.
+    let mut stream = std::net::TcpStream::connect(address)?;
+    stream.write_all(&[b'a'; 64 * 1024])?;
+    let mut replies = String::new();
+    stream.read_to_string(&mut replies)?;
+    assert_eq!(replies, "-ERR Protocol error: too big inline request\r\n");
+
+    // The server is still there for the next client.
+    let mut stream = std::net::TcpStream::connect(address)?;
+    stream.write_all(resp_command(&["PING"]).as_bytes())?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+    let mut replies = String::new();
+    stream.read_to_string(&mut replies)?;
+    assert_eq!(replies, "+PONG\r\n");
+
+    Ok(())
+}
+
+// Send an HTTP request to a server and return the status line, the ETag
+// header if any, and the body of the response.
+fn http_request(
+    server: &Server,
+    method: &str,
+    target: &str,
+    headers: &[&str],
+    body: &str,
+) -> Result<(String, Option<String>, String), Box<dyn std::error::Error>> {
+    let mut stream = std::net::TcpStream::connect(server.address.strip_prefix("tcp:").unwrap())?;
+    let mut request = format!("{} {} HTTP/1.1\r\nHost: localhost\r\n", method, target);
+    for header in headers {
+        request.push_str(&format!("{}\r\n", header));
+    }
+    request.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+    stream.write_all(request.as_bytes())?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response.split_once("\r\n\r\n").ok_or("no end of headers")?;
+    let mut lines = head.split("\r\n");
+    let status = lines.next().unwrap_or_default().to_string();
+    let etag = lines
+        .find_map(|line| line.strip_prefix("ETag: "))
+        .map(String::from);
+    Ok((status, etag, body.to_string()))
+}
+
+#[test]
+fn serve_http() -> TestResult {
+    let dir = TempDir::new()?;
+    let server = Server::start(&dir, &["--listen", "tcp:127.0.0.1:0", "--protocol", "http"])?;
+    let etag = |version: u64| Some(format!("\"{}\"", version));
+
+    assert_eq!(
+        http_request(&server, "PUT", "/keys/app%2Fname", &[], "kv")?,
+        (
+            "HTTP/1.1 201 Created".to_string(),
+            etag(1),
+            "{\"key\":\"app/name\",\"version\":1}\n".to_string()
+        )
+    );
+    assert_eq!(
+        http_request(&server, "PUT", "/keys/app%2Fname", &[], "other")?.0,
+        "HTTP/1.1 409 Conflict"
+    );
+    assert_eq!(
+        http_request(&server, "PUT", "/keys/app%2Fname?force=true", &[], "kv2")?.1,
+        etag(2)
+    );
+    assert_eq!(
+        http_request(&server, "GET", "/keys/app%2Fname", &[], "")?,
+        (
+            "HTTP/1.1 200 OK".to_string(),
+            etag(2),
+            "{\"key\":\"app/name\",\"value\":\"kv2\",\"version\":2}\n".to_string()
+        )
+    );
+
+    // Conditional updates compare the ETag with the key's version.
+    let stale = http_request(
+        &server,
+        "PUT",
+        "/keys/app%2Fname",
+        &["If-Match: \"1\""],
+        "x",
+    )?;
+    assert_eq!(stale.0, "HTTP/1.1 412 Precondition Failed");
+    assert_eq!(
+        stale.2,
+        "{\"error\":\"Precondition failed: 'app/name' is at version 2, not 1.\"}\n"
+    );
+    assert_eq!(
+        http_request(
+            &server,
+            "PUT",
+            "/keys/app%2Fname",
+            &["If-Match: \"2\""],
+            "kv3"
+        )?
+        .1,
+        etag(3)
+    );
+    assert_eq!(
+        http_request(
+            &server,
+            "PUT",
+            "/keys/app%2Fname",
+            &["If-None-Match: *"],
+            "x"
+        )?
+        .0,
+        "HTTP/1.1 412 Precondition Failed"
+    );
+    assert_eq!(
+        http_request(&server, "PUT", "/keys/user", &["If-None-Match: *"], "bob")?.0,
+        "HTTP/1.1 201 Created"
+    );
+
+    assert_eq!(
+        http_request(&server, "GET", "/keys?prefix=app", &[], "")?.2,
+        "[{\"key\":\"app/name\",\"value\":\"kv3\"}]\n"
+    );
+    assert_eq!(
+        http_request(&server, "GET", "/keys", &[], "")?.2,
+        "[{\"key\":\"app/name\",\"value\":\"kv3\"},{\"key\":\"user\",\"value\":\"bob\"}]\n"
+    );
+
+    assert_eq!(
+        http_request(&server, "DELETE", "/keys/user", &["If-Match: \"2\""], "")?.0,
+        "HTTP/1.1 412 Precondition Failed"
+    );
+    assert_eq!(
+        http_request(&server, "DELETE", "/keys/user", &[], "")?,
+        (
+            "HTTP/1.1 200 OK".to_string(),
+            None,
+            "{\"key\":\"user\",\"value\":\"bob\"}\n".to_string()
+        )
+    );
+    assert_eq!(
+        http_request(&server, "GET", "/keys/user", &[], "")?,
+        (
+            "HTTP/1.1 404 Not Found".to_string(),
+            None,
+            "{\"error\":\"No entry found for key 'user'.\"}\n".to_string()
+        )
+    );
+    assert_eq!(
+        http_request(&server, "POST", "/keys/user", &[], "")?.0,
+        "HTTP/1.1 405 Method Not Allowed"
+    );
+    assert_eq!(
+        http_request(&server, "GET", "/other", &[], "")?.0,
+        "HTTP/1.1 404 Not Found"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn watch_key() -> TestResult {
+    let dir = TempDir::new()?;
+    kvstore(&dir)?
+        .args(["set", "foo", "bar"])
+        .assert()
+        .success();
+    let mut watcher = Watcher::start(&dir, &["watch", "foo"])?;
+
+    kvstore(&dir)?
+        .args(["set", "-f", "foo", "baz"])
+        .assert()
+        .success();
+    assert_eq!(watcher.next_line()?, "set foo : baz (was bar)\n");
+    kvstore(&dir)?
+        .args(["set", "other", "1"])
+        .assert()
+        .success();
+    kvstore(&dir)?.args(["remove", "foo"]).assert().success();
+    assert_eq!(watcher.next_line()?, "remove foo (was baz)\n");
+    kvstore(&dir)?
+        .args(["set", "--ttl", "1", "foo", "brief"])
+        .assert()
+        .success();
+    assert_eq!(watcher.next_line()?, "set foo : brief\n");
+    assert_eq!(watcher.next_line()?, "expire foo (was brief)\n");
+
+    Ok(())
+}
+
+#[test]
+fn watch_prefix() -> TestResult {
+    let dir = TempDir::new()?;
+    let mut watcher = Watcher::start(&dir, &["-o", "json", "watch", "--prefix", "app."])?;
+
+    kvstore(&dir)?
+        .args(["set", "app.name", "kv"])
+        .assert()
+        .success();
+    assert_eq!(
+        watcher.next_line()?,
+        "{\"event\":\"set\",\"key\":\"app.name\",\"new\":\"kv\",\"old\":null}\n"
+    );
+    kvstore(&dir)?
+        .args(["set", "name", "kv"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["remove", "app.name"])
+        .assert()
+        .success();
+    assert_eq!(
+        watcher.next_line()?,
+        "{\"event\":\"remove\",\"key\":\"app.name\",\"new\":null,\"old\":\"kv\"}\n"
+    );
+
+    kvstore(&dir)?
+        .args(["watch", "foo", "--prefix", "app."])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+fn watch_remote() -> TestResult {
+    let dir = TempDir::new()?;
+    let server = Server::start(&dir, &["--listen", "tcp:127.0.0.1:0"])?;
+    let remote = |args: &[&str]| -> Result<Command, Box<dyn std::error::Error>> {
+        let mut cmd = kvstore(&dir)?;
+        cmd.args(["--remote", &server.address]).args(args);
+        Ok(cmd)
+    };
+    let mut watcher = Watcher::start(
+        &dir,
+        &[
+            "--remote",
+            &server.address,
+            "-o",
+            "tsv",
+            "watch",
+            "--prefix",
+            "app.",
+        ],
+    )?;
+
+    remote(&["set", "app.a", "one\ttwo"])?.assert().success();
+    assert_eq!(watcher.next_line()?, "set\tapp.a\t\tone\\ttwo\n");
+    remote(&["set", "b", "2"])?.assert().success();
+    remote(&["set", "-f", "app.a", "1"])?.assert().success();
+    assert_eq!(watcher.next_line()?, "set\tapp.a\tone\\ttwo\t1\n");
+    remote(&["set", "--ttl", "1", "app.b", "brief"])?
+        .assert()
+        .success();
+    assert_eq!(watcher.next_line()?, "set\tapp.b\t\tbrief\n");
+    assert_eq!(watcher.next_line()?, "expire\tapp.b\tbrief\t\n");
+
+    // A namespace that doesn't exist ends the watch.
+    remote(&["--ns", "missing", "watch", "app.a"])?
+        .assert()
+        .code(9);
+
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn watchers_that_disconnect_are_let_go() -> TestResult {
+    let dir = TempDir::new()?;
+    let server = Server::start(&dir, &["--listen", "tcp:127.0.0.1:0"])?;
+    let threads = || fs::read_dir(format!("/proc/{}/task", server.child.id())).map(Iterator::count);
+    let idle = threads()?;
+
+    let mut watchers = Vec::new();
+    for _ in 0..5 {
+        let stream = std::net::TcpStream::connect(server.address.strip_prefix("tcp:").unwrap())?;
+        (&stream).write_all(b"WATCH\tfoo\n")?;
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        assert_eq!(line, "OK\n");
+        watchers.push(stream);
+    }
+    assert!(threads()? >= idle + 5);
+
+    // Nothing changes for the watchers to report, but they're still noticed
+    // going away.
+    drop(watchers);
+    for _ in 0..50 {
+        if threads()? == idle {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Err(format!("{} watcher threads left behind", threads()? - idle).into())
+}
+
+#[test]
+fn backends() -> TestResult {
+    for backend in ["json", "sqlite"] {
+        let dir = TempDir::new()?;
+        let run = |args: &[&str]| -> Result<Command, Box<dyn std::error::Error>> {
+            let mut cmd = kvstore(&dir)?;
+            cmd.env("KVSTORE_BACKEND", backend).args(args);
+            Ok(cmd)
+        };
+
+        run(&["set", "foo", "bar"])?.assert().success();
+        run(&["set", "-f", "foo", "baz"])?.assert().success();
+        run(&["--ns", "missing", "get", "foo"])?.assert().code(9);
+        run(&["ns", "create", "app"])?.assert().success();
+        run(&["--ns", "app", "set", "name", "kv"])?
+            .assert()
+            .success();
+        run(&["get", "foo"])?
+            .assert()
+            .success()
+            .stdout("foo : baz\n");
+        run(&["history", "foo"])?
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("bar"));
+        run(&["--ns", "app", "list"])?
+            .assert()
+            .success()
+            .stdout("name : kv\n");
+        run(&["--repair", "get", "foo"])?
+            .assert()
+            .failure()
+            .stderr("--repair only works with the tsv backend.\n");
+
+        // Each backend stores the database in a file of its own format,
+        // without a write-ahead log.
+        let contents = fs::read(dir.path().join("kv.db"))?;
+        match backend {
+            "json" => {
+                let contents = String::from_utf8(contents)?;
+                assert!(contents.contains("\"value\": \"baz\""), "{}", contents);
+            }
+            _ => assert!(contents.starts_with(b"SQLite format 3\0")),
+        }
+        assert!(!dir.path().join("kv.db.log").exists());
+    }
+
+    // Nothing is kept once the process exits.
+    let dir = TempDir::new()?;
+    kvstore(&dir)?
+        .args(["--backend", "memory", "set", "foo", "bar"])
+        .assert()
+        .success();
+    kvstore(&dir)?
+        .args(["--backend", "memory", "get", "foo"])
+        .assert()
+        .code(3);
+    assert!(!dir.path().join("kv.db").exists());
+
+    Ok(())
+}
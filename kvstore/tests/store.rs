@@ -0,0 +1,255 @@
+use kvstore::{BackendKind, Database, DbError, Lock, MemoryStore, Store};
+use std::time::Duration;
+use tempfile::TempDir;
+
+type TestResult = Result<(), Box<dyn std::error::Error>>;
+
+// Exercise the operations every store should support the same way.
+fn check_store(store: &mut dyn Store) -> TestResult {
+    store.insert("b".to_string(), b"2".to_vec(), false)?;
+    store.insert("a".to_string(), b"1".to_vec(), false)?;
+    assert!(matches!(
+        store.insert("a".to_string(), b"x".to_vec(), false),
+        Err(DbError::AlreadyExists(key)) if key == "a"
+    ));
+    store.insert("a".to_string(), vec![0, 0xff], true)?;
+
+    assert_eq!(store.get("a"), Some((&"a".to_string(), &[0, 0xff][..])));
+    assert_eq!(store.get("c"), None);
+    let keys: Vec<&String> = store.iter().map(|(k, _)| k).collect();
+    assert_eq!(keys, ["a", "b"]);
+
+    assert_eq!(store.remove("b")?, Some(("b".to_string(), b"2".to_vec())));
+    assert_eq!(store.remove("b")?, None);
+    store.flush()?;
+
+    Ok(())
+}
+
+#[test]
+fn memory_store() -> TestResult {
+    check_store(&mut MemoryStore::new())
+}
+
+#[test]
+fn database_store() -> TestResult {
+    let dir = TempDir::new()?;
+    let path = dir.path().join("kv.db");
+    let path = path.to_str().unwrap();
+
+    let mut db = Database::from_disk(path, BackendKind::Tsv, Lock::Exclusive, false, None)?;
+    check_store(&mut db)?;
+    drop(db);
+
+    // Flushing wrote the database file and emptied the log.
+    assert_eq!(
+        std::fs::read(dir.path().join("kv.db.log"))?,
+        b"#kvstore v2\n"
+    );
+    let db = Database::from_disk(path, BackendKind::Tsv, Lock::Shared, false, None)?;
+    let entries: Vec<(&String, &[u8])> = Store::iter(&db).collect();
+    assert_eq!(entries, [(&"a".to_string(), &[0, 0xff][..])]);
+
+    Ok(())
+}
+
+#[test]
+fn read_only_database() -> TestResult {
+    let dir = TempDir::new()?;
+    let path = dir.path().join("kv.db");
+    let path = path.to_str().unwrap();
+
+    // Reading a database that doesn't exist yet doesn't create it.
+    let mut db = Database::open_read_only(path, BackendKind::Tsv, false, None)?;
+    assert_eq!(db.get("a"), None);
+    assert!(matches!(
+        db.insert("a".to_string(), b"1".to_vec(), false),
+        Err(DbError::Io(_))
+    ));
+    db.commit()?;
+    drop(db);
+    assert!(!dir.path().join("kv.db").exists());
+    assert!(!dir.path().join("kv.db.log").exists());
+
+    let mut db = Database::from_disk(path, BackendKind::Tsv, Lock::Exclusive, false, None)?;
+    db.insert("a".to_string(), b"1".to_vec(), false)?;
+    drop(db);
+    let before = std::fs::read(dir.path().join("kv.db.log"))?;
+
+    let db = Database::open_read_only(path, BackendKind::Tsv, false, None)?;
+    assert_eq!(db.get("a"), Some((&"a".to_string(), &b"1"[..])));
+    assert!(!db.is_dirty());
+    drop(db);
+    assert_eq!(std::fs::read(dir.path().join("kv.db.log"))?, before);
+
+    Ok(())
+}
+
+#[test]
+fn commit_compacts_log() -> TestResult {
+    let dir = TempDir::new()?;
+    let path = dir.path().join("kv.db");
+    let path = path.to_str().unwrap();
+
+    let mut db = Database::from_disk(path, BackendKind::Tsv, Lock::Exclusive, false, None)?;
+    assert!(!db.is_dirty());
+    db.insert("a".to_string(), b"1".to_vec(), false)?;
+    assert!(db.is_dirty());
+    db.commit()?;
+    assert!(!db.is_dirty());
+
+    assert_eq!(
+        std::fs::read(dir.path().join("kv.db"))?,
+        b"#kvstore v2\na\t1\n"
+    );
+    assert_eq!(
+        std::fs::read(dir.path().join("kv.db.log"))?,
+        b"#kvstore v2\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn backends_keep_the_same_database() -> TestResult {
+    for backend in [BackendKind::Tsv, BackendKind::Json, BackendKind::Sqlite] {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("kv.db");
+        let path = path.to_str().unwrap();
+
+        let mut db = Database::open(path, backend, Lock::Exclusive, false, None)?;
+        check_store(&mut db)?;
+        db.insert("a".to_string(), b"3".to_vec(), true)?;
+        db.insert_with_ttl(
+            "t".to_string(),
+            b"1".to_vec(),
+            false,
+            Duration::from_secs(60),
+        )?;
+        db.create_namespace("other")?;
+        db.use_namespace("other")?;
+        db.transaction(|tx| {
+            tx.insert("x".to_string(), b"1".to_vec(), false)?;
+            tx.insert("y".to_string(), b"2".to_vec(), false)
+        })?;
+        db.create_namespace("gone")?;
+        db.drop_namespace("gone")?;
+        drop(db);
+
+        let mut db = Database::open_read_only(path, backend, false, None)?;
+        assert_eq!(
+            db.get("a"),
+            Some((&"a".to_string(), &b"3"[..])),
+            "{}",
+            backend
+        );
+        assert_eq!(db.version("a"), Some(3), "{}", backend);
+        let history: Vec<&[u8]> = db
+            .history("a")
+            .unwrap()
+            .iter()
+            .map(|r| &r.value[..])
+            .collect();
+        assert_eq!(history, [&b"1"[..], &[0, 0xff][..]], "{}", backend);
+        assert!(
+            db.ttl("t").is_some_and(|ttl| ttl > Duration::from_secs(50)),
+            "{}",
+            backend
+        );
+        let namespaces: Vec<&String> = db.namespaces().collect();
+        assert_eq!(namespaces, ["default", "other"], "{}", backend);
+        db.use_namespace("other")?;
+        assert_eq!(db.iter().count(), 2, "{}", backend);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn memory_backend_keeps_nothing() -> TestResult {
+    let dir = TempDir::new()?;
+    let path = dir.path().join("kv.db");
+    let path = path.to_str().unwrap();
+
+    let mut db = Database::open(path, BackendKind::Memory, Lock::Exclusive, false, None)?;
+    check_store(&mut db)?;
+    assert!(db.get("a").is_some());
+    drop(db);
+    assert_eq!(std::fs::read_dir(dir.path())?.count(), 0);
+
+    let db = Database::open(path, BackendKind::Memory, Lock::Exclusive, false, None)?;
+    assert_eq!(db.get("a"), None);
+
+    // Only the TSV backend can encrypt a database.
+    assert!(matches!(
+        Database::open(
+            path,
+            BackendKind::Json,
+            Lock::Exclusive,
+            false,
+            Some(b"secret")
+        ),
+        Err(DbError::Io(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn failed_json_write_leaves_database_unchanged() -> TestResult {
+    let dir = TempDir::new()?;
+    let path = dir.path().join("kv.json");
+    let path = path.to_str().unwrap();
+
+    let mut db = Database::open(path, BackendKind::Json, Lock::Exclusive, false, None)?;
+    db.insert("a".to_string(), b"1".to_vec(), false)?;
+
+    // A directory where the file is written before it's renamed into place
+    // makes writing it fail.
+    std::fs::create_dir(dir.path().join("kv.json.tmp"))?;
+    assert!(db.insert("b".to_string(), b"2".to_vec(), false).is_err());
+    assert_eq!(db.get("b"), None);
+
+    std::fs::remove_dir(dir.path().join("kv.json.tmp"))?;
+    db.insert("c".to_string(), b"3".to_vec(), false)?;
+    drop(db);
+    let db = Database::open(path, BackendKind::Json, Lock::Shared, false, None)?;
+    let keys: Vec<&String> = db.iter().map(|(k, _)| k).collect();
+    assert_eq!(keys, ["a", "c"]);
+
+    Ok(())
+}
+
+#[test]
+fn replaying_compacted_log_changes_nothing() -> TestResult {
+    let dir = TempDir::new()?;
+    let path = dir.path().join("kv.db");
+    let path = path.to_str().unwrap();
+    let log_path = dir.path().join("kv.db.log");
+
+    let mut db = Database::from_disk(path, BackendKind::Tsv, Lock::Exclusive, false, None)?;
+    db.insert("a".to_string(), b"1".to_vec(), false)?;
+    db.commit()?;
+    for value in ["2", "3"] {
+        db.insert("a".to_string(), value.as_bytes().to_vec(), true)?;
+    }
+    let log = std::fs::read(&log_path)?;
+    db.commit()?;
+    drop(db);
+
+    // As if the process crashed after writing the database file but before
+    // truncating the log.
+    std::fs::write(&log_path, log)?;
+    let db = Database::from_disk(path, BackendKind::Tsv, Lock::Shared, false, None)?;
+    assert_eq!(db.get("a"), Some((&"a".to_string(), &b"3"[..])));
+    assert_eq!(db.version("a"), Some(3));
+    let history: Vec<(u64, &[u8])> = db
+        .history("a")
+        .unwrap()
+        .iter()
+        .map(|revision| (revision.version, revision.value.as_slice()))
+        .collect();
+    assert_eq!(history, [(1, &b"1"[..]), (2, &b"2"[..])]);
+
+    Ok(())
+}